@@ -0,0 +1,23 @@
+// Shows the notifications sent by the server's Web Push sender, which look like
+// {"title": "...", "body": "...", "tag": "line:victoria", "url": "/"}.
+self.addEventListener("push", (event) => {
+  const data = event.data ? event.data.json() : {};
+  event.waitUntil(
+    self.registration.showNotification(data.title ?? "Severe Delays", {
+      body: data.body,
+      tag: data.tag,
+      data: {url: data.url ?? "/"},
+    })
+  );
+});
+
+self.addEventListener("notificationclick", (event) => {
+  event.notification.close();
+  const url = new URL(event.notification.data?.url ?? "/", self.location.origin).href;
+  event.waitUntil(
+    self.clients.matchAll({type: "window"}).then((clients) => {
+      const existing = clients.find((client) => client.url === url);
+      return existing ? existing.focus() : self.clients.openWindow(url);
+    })
+  );
+});
@@ -1,8 +1,9 @@
-import {type Component, createEffect, createSignal} from "solid-js";
+import {type Component, Show, createEffect, createSignal} from "solid-js";
 import {type SetStoreFunction, type Store, createStore} from "solid-js/store";
 import {Button} from "../components/Button";
 import {Popover} from "../Popover";
 import feather from "feather-icons";
+import {disablePushNotifications, enablePushNotifications, isPushSupported} from "../api/push";
 
 const LOCAL_STORAGE_KEY = "settings";
 
@@ -12,6 +13,7 @@ export interface Settings {
   favouriteLines: string[];
   favouriteStations: string[];
   includeStationInformationInStats: boolean;
+  pushNotifications: boolean;
 }
 
 type SerializedSettings = Partial<Settings>;
@@ -67,6 +69,7 @@ const SettingsPopoverContent: Component<SettingsProps> = (props) => {
   let includeClosedInStatsCheckbox: HTMLInputElement | undefined;
   let includePlannedClosuresInStatsCheckbox: HTMLInputElement | undefined;
   let includeStationInformationInStatsCheckbox: HTMLInputElement | undefined;
+  let pushNotificationsCheckbox: HTMLInputElement | undefined;
 
   return (
     <div class="space-y-3">
@@ -119,6 +122,30 @@ const SettingsPopoverContent: Component<SettingsProps> = (props) => {
           Show informational messages
         </label>
       </div>
+
+      <Show when={isPushSupported()}>
+        <h3 class="font-semibold text-lg pt-2">Notifications</h3>
+        <div>
+          <label>
+            <input
+              type="checkbox"
+              class="mr-2"
+              ref={pushNotificationsCheckbox}
+              checked={props.store.pushNotifications}
+              onInput={() => {
+                const enabled = pushNotificationsCheckbox!.checked;
+                props.setStore({pushNotifications: enabled});
+                if (!enabled) {
+                  disablePushNotifications().catch((e) =>
+                    console.error("Failed to disable push notifications", e)
+                  );
+                }
+              }}
+            />
+            Notify me when my favourites change
+          </label>
+        </div>
+      </Show>
     </div>
   );
 };
@@ -138,12 +165,21 @@ export const createSettingsStore: () => [Settings, SetStoreFunction<Settings>] =
       favouriteLines: [],
       favouriteStations: [],
       includeStationInformationInStats: false,
+      pushNotifications: false,
     },
     existingSettings
   );
 
   const [store, setStore] = createStore<Settings>(settingsWithDefaults);
   createEffect(() => updatePersistedSettings(store));
+  // Keep the server's list of lines and stations to notify about in sync with the favourites
+  createEffect(() => {
+    if (store.pushNotifications && isPushSupported()) {
+      enablePushNotifications([...store.favouriteLines], [...store.favouriteStations]).catch((e) =>
+        console.error("Failed to enable push notifications", e)
+      );
+    }
+  });
   return [store, setStore];
 };
 
@@ -383,22 +383,30 @@ export const StatusHistory: Component = () => {
 const mapLineState = (state: string | undefined): LineState => {
   switch (state) {
     case "GoodService":
+    case "NoIssues":
       return LineState.GOOD_SERVICE;
     case "MinorDelays":
+    case "IssuesReported":
       return LineState.MINOR_DELAYS;
     case "SevereDelays":
       return LineState.SEVERE_DELAYS;
     case "PartClosure":
+    case "PartClosed":
       return LineState.PART_CLOSURE;
     case "PlannedClosure":
       return LineState.PLANNED_CLOSURE;
     case "ServiceClosed":
       return LineState.SERVICE_CLOSED;
     case "PartSuspended":
+    case "BusService":
       return LineState.PART_SUSPENDED;
     case "Suspended":
+    case "Closed":
+    case "NotRunning":
       return LineState.SUSPENDED;
     case "ReducedService":
+    case "ChangeOfFrequency":
+    case "Diverted":
       return LineState.REDUCED_SERVICE;
     default:
       return LineState.OTHER;
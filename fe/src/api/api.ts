@@ -1,4 +1,9 @@
-import type {LineApiResponse, StationApiResponse, StationDetailsApiResponse} from "./types";
+import type {
+  CollectionGapsApiResponse,
+  LineApiResponse,
+  StationApiResponse,
+  StationDetailsApiResponse,
+} from "./types";
 
 export const loadLineStatuses = async (from: Date, to: Date): Promise<LineApiResponse> => {
   const base = localStorage.getItem("apiBaseUri") || "";
@@ -18,6 +23,18 @@ export const loadStationStatuses = async (from: Date, to: Date): Promise<Station
   return data;
 };
 
+export const loadCollectionGaps = async (
+  from: Date,
+  to: Date
+): Promise<CollectionGapsApiResponse> => {
+  const base = localStorage.getItem("apiBaseUri") || "";
+  const response = await fetch(
+    `${base}/api/v1/history/gaps?from=${from.toISOString()}&to=${to.toISOString()}`
+  );
+  const data = await response.json();
+  return data;
+};
+
 export const loadStationDetails = async (): Promise<StationDetailsApiResponse> => {
   try {
     const base = localStorage.getItem("apiBaseUri") || "";
@@ -0,0 +1,56 @@
+const WORKER_PATH = "/push-worker.js";
+
+export const isPushSupported = (): boolean =>
+  "serviceWorker" in navigator && "PushManager" in window && "Notification" in window;
+
+const decodeBase64Url = (value: string): Uint8Array => {
+  const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+  const binary = atob(base64.padEnd(base64.length + ((4 - (base64.length % 4)) % 4), "="));
+  return Uint8Array.from(binary, (char) => char.charCodeAt(0));
+};
+
+/**
+ * Subscribes this browser to notifications about the given lines and stations, or every line and
+ * station if both are empty. Calling it again replaces the lines and stations.
+ */
+export const enablePushNotifications = async (
+  lines: string[],
+  stations: string[]
+): Promise<void> => {
+  const base = localStorage.getItem("apiBaseUri") || "";
+  const registration = await navigator.serviceWorker.register(WORKER_PATH);
+  let subscription = await registration.pushManager.getSubscription();
+  if (!subscription) {
+    const keyResponse = await fetch(`${base}/api/v1/push/public-key`);
+    if (!keyResponse.ok) {
+      throw new Error(`Push notifications are unavailable: ${keyResponse.status}`);
+    }
+    const {publicKey} = await keyResponse.json();
+    subscription = await registration.pushManager.subscribe({
+      userVisibleOnly: true,
+      applicationServerKey: decodeBase64Url(publicKey),
+    });
+  }
+  const response = await fetch(`${base}/api/v1/push/subscriptions`, {
+    method: "POST",
+    headers: {"Content-Type": "application/json"},
+    body: JSON.stringify({subscription: subscription.toJSON(), lines, stations}),
+  });
+  if (!response.ok) {
+    throw new Error(`Failed to register for push notifications: ${response.status}`);
+  }
+};
+
+export const disablePushNotifications = async (): Promise<void> => {
+  const base = localStorage.getItem("apiBaseUri") || "";
+  const registration = await navigator.serviceWorker.getRegistration(WORKER_PATH);
+  const subscription = await registration?.pushManager.getSubscription();
+  if (!subscription) {
+    return;
+  }
+  await fetch(
+    `${base}/api/v1/push/subscriptions?endpoint=${encodeURIComponent(subscription.endpoint)}`,
+    {method: "DELETE"}
+  );
+  await subscription.unsubscribe();
+};
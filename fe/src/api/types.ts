@@ -16,18 +16,44 @@ export interface LineStatus {
 export interface LineStatusEntry {
   status: LineStatusEnum;
   reason?: string;
+  validityPeriods: ValidityPeriod[];
+  disruption?: Disruption;
+}
+
+export interface ValidityPeriod {
+  from: string; // datetime
+  to: string; // datetime
+  isNow: boolean;
+}
+
+export interface Disruption {
+  category?: string; // e.g. "RealTime" or "PlannedWork"
+  description?: string;
+  additionalInfo?: string;
+  closureText?: string;
 }
 
 export type LineStatusEnum =
+  | "Closed"
   | "Suspended"
+  | "NotRunning"
   | "PartSuspended"
   | "PlannedClosure"
   | "PartClosure"
+  | "PartClosed"
   | "ServiceClosed"
   | "SevereDelays"
+  | "BusService"
   | "ReducedService"
+  | "Diverted"
+  | "ChangeOfFrequency"
   | "MinorDelays"
+  | "IssuesReported"
+  | "ExitOnly"
+  | "NoStepFreeAccess"
+  | "Information"
   | "GoodService"
+  | "NoIssues"
   | "Other"
   | string;
 
@@ -41,6 +67,17 @@ export interface StationApiResponse {
   };
 }
 
+/** Periods when the status wasn't being collected, so nothing is known about them. */
+export interface CollectionGapsApiResponse {
+  lines: CollectionGap[];
+  stations: CollectionGap[];
+}
+
+export interface CollectionGap {
+  from: string; // datetime
+  to?: string; // datetime, unset while the gap is still going on
+}
+
 export interface StationDetailsApiResponse {
   [stationId: string]: StationDetail;
 }
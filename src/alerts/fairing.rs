@@ -0,0 +1,40 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::spawn;
+use rocket::{Orbit, Rocket};
+
+use super::Alerts;
+use crate::config::Config;
+use crate::email::Mailer;
+use crate::events::EventBus;
+use crate::store::Store;
+
+pub struct AlertFairing;
+
+impl AlertFairing {
+    pub fn new() -> Self {
+        AlertFairing
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AlertFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Subscription Alert Task",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.state::<Config>().unwrap();
+        let alerts = Alerts::new(
+            config.webhooks.clone(),
+            rocket.state::<Mailer>().cloned(),
+            rocket.state::<Store>().unwrap().clone(),
+        );
+        let events = rocket.state::<EventBus>().unwrap().clone();
+        spawn(async move {
+            alerts.start(events).await;
+        });
+    }
+}
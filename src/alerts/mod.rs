@@ -0,0 +1,326 @@
+mod fairing;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use rocket::tokio::{self, sync::broadcast::error::RecvError};
+use serde::Serialize;
+use time::{format_description, OffsetDateTime};
+
+use crate::config::WebhookConfig;
+use crate::email::{self, Mailer};
+use crate::events::{EventBus, StatusEvent};
+use crate::localtime;
+use crate::store::{Store, StoredSubscription};
+use crate::types::{LineState, LineStatus, LineTransition, NotificationTarget, Subscription};
+use crate::webhooks;
+
+pub use fairing::AlertFairing;
+
+/// Checks each line transition against the alert subscriptions, and notifies the matching ones.
+pub struct Alerts {
+    webhook_config: WebhookConfig,
+    client: reqwest::Client,
+    mailer: Option<Mailer>,
+    store: Store,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertPayload<'a> {
+    subscription_id: i64,
+    subscription_name: &'a str,
+    line: &'a str,
+    mode: Option<&'a str>,
+    time: String,
+    previous: &'a [LineStatus],
+    current: &'a [LineStatus],
+}
+
+impl Alerts {
+    pub fn new(webhook_config: WebhookConfig, mailer: Option<Mailer>, store: Store) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(webhook_config.timeout_secs))
+            .build()
+            .unwrap();
+        Alerts {
+            webhook_config,
+            client,
+            mailer,
+            store,
+        }
+    }
+
+    pub async fn start(self, events: EventBus) {
+        let alerts = Arc::new(self);
+        let mut receiver = events.subscribe();
+        loop {
+            match receiver.recv().await {
+                Ok(StatusEvent::Line(transition)) => alerts.evaluate(&transition).await,
+                Ok(StatusEvent::Station(_)) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Alerts fell behind, skipped {} events", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    async fn evaluate(self: &Arc<Self>, transition: &LineTransition) {
+        let subscriptions = match self.store.get_connection().await {
+            Ok(mut connection) => match connection.get_subscriptions().await {
+                Ok(subscriptions) => subscriptions,
+                Err(err) => {
+                    warn!("Failed to load subscriptions: {:?}", err);
+                    return;
+                }
+            },
+            Err(err) => {
+                warn!(
+                    "Failed to acquire DB connection to load subscriptions: {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        for subscription in subscriptions {
+            if !should_alert(&subscription.subscription, transition) {
+                continue;
+            }
+            let alerts = self.clone();
+            let transition = transition.clone();
+            tokio::spawn(async move {
+                alerts.notify(&subscription, &transition).await;
+            });
+        }
+    }
+
+    async fn notify(&self, subscription: &StoredSubscription, transition: &LineTransition) {
+        match &subscription.subscription.target {
+            NotificationTarget::Webhook { url, secret } => {
+                self.notify_webhook(subscription, transition, url, secret)
+                    .await
+            }
+            NotificationTarget::Email { address } => {
+                self.notify_email(subscription, transition, address).await
+            }
+        }
+    }
+
+    async fn notify_webhook(
+        &self,
+        subscription: &StoredSubscription,
+        transition: &LineTransition,
+        url: &str,
+        secret: &str,
+    ) {
+        let payload = AlertPayload {
+            subscription_id: subscription.id,
+            subscription_name: &subscription.subscription.name,
+            line: &transition.line,
+            mode: transition.mode.as_deref(),
+            time: transition
+                .time
+                .format(&format_description::well_known::Rfc3339)
+                .unwrap(),
+            previous: transition.previous.as_deref().unwrap_or_default(),
+            current: transition.current.as_deref().unwrap_or_default(),
+        };
+        let payload = match serde_json::to_string(&payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Failed to serialize alert payload: {:?}", err);
+                return;
+            }
+        };
+        let result =
+            webhooks::send_with_retries(&self.client, &self.webhook_config, url, secret, &payload)
+                .await;
+        if let Err(failure) = result {
+            warn!(
+                "Failed to send alert for subscription {}: {:?}",
+                subscription.id, failure
+            );
+        }
+    }
+
+    async fn notify_email(
+        &self,
+        subscription: &StoredSubscription,
+        transition: &LineTransition,
+        address: &str,
+    ) {
+        let Some(mailer) = &self.mailer else {
+            warn!(
+                "Can't send alert for subscription {} because email isn't configured",
+                subscription.id
+            );
+            return;
+        };
+        let current = transition.current.as_deref().unwrap_or_default();
+        let subject = match worst_state(current) {
+            Some(state) => format!("{}: {}", transition.line, state.description()),
+            None => transition.line.clone(),
+        };
+        let body = format!(
+            "The status of {} changed at {}, which matched your alert \"{}\".\n\n\
+            Now:\n{}\nPreviously:\n{}",
+            transition.line,
+            localtime::describe(transition.time),
+            subscription.subscription.name,
+            email::describe_statuses(current),
+            email::describe_statuses(transition.previous.as_deref().unwrap_or_default()),
+        );
+        if let Err(err) = mailer.send(address, &subject, body).await {
+            warn!(
+                "Failed to send alert for subscription {}: {}",
+                subscription.id, err
+            );
+        }
+    }
+}
+
+/// Whether a transition should trigger a subscription's alert: the line must be watched, the
+/// subscription must be active at the time, and the line must have just reached the minimum
+/// severity or got even worse. Lines that we didn't know about before never trigger alerts.
+///
+/// A line closing for the night isn't a disruption, so it never alerts, and a line which reopens
+/// is compared as if it had no previous status.
+fn should_alert(subscription: &Subscription, transition: &LineTransition) -> bool {
+    let watched = subscription.lines.is_empty() || subscription.lines.contains(&transition.line);
+    let (Some(previous), Some(current)) = (&transition.previous, &transition.current) else {
+        return false;
+    };
+    let Some(current) = worst_disruption(current) else {
+        return false;
+    };
+    let got_worse = worst_disruption(previous).is_none_or(|previous| current < previous);
+    watched
+        && current.is_disruption_at_least(subscription.min_severity)
+        && got_worse
+        && is_active(subscription, transition.time)
+}
+
+/// Whether alerts should be sent for the subscription at `time`.
+fn is_active(subscription: &Subscription, time: OffsetDateTime) -> bool {
+    let local = localtime::to_london(time);
+    if !subscription.days.is_empty() && !subscription.days.contains(&local.weekday()) {
+        return false;
+    }
+    match (subscription.active_from, subscription.active_until) {
+        (Some(from), Some(until)) if from <= until => local.time() >= from && local.time() < until,
+        // The window spans midnight
+        (Some(from), Some(until)) => local.time() >= from || local.time() < until,
+        (Some(from), None) => local.time() >= from,
+        (None, Some(until)) => local.time() < until,
+        (None, None) => true,
+    }
+}
+
+fn worst_state(statuses: &[LineStatus]) -> Option<LineState> {
+    statuses.iter().map(|status| status.status).min()
+}
+
+/// The most severe state other than `ServiceClosed`, which only means that it's outside the line's
+/// operating hours.
+fn worst_disruption(statuses: &[LineStatus]) -> Option<LineState> {
+    statuses
+        .iter()
+        .map(|status| status.status)
+        .filter(|state| *state != LineState::ServiceClosed)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(min_severity: LineState) -> Subscription {
+        Subscription {
+            name: "Commute".to_string(),
+            lines: vec!["central".to_string()],
+            min_severity,
+            active_from: None,
+            active_until: None,
+            days: vec![],
+            target: NotificationTarget::Email {
+                address: "commuter@example.com".to_string(),
+            },
+        }
+    }
+
+    fn transition(time: &str, previous: LineState, current: LineState) -> LineTransition {
+        let statuses = |state| {
+            Some(vec![LineStatus {
+                status: state,
+                reason: None,
+                validity_periods: vec![],
+                disruption: None,
+            }])
+        };
+        LineTransition {
+            line: "central".to_string(),
+            time: OffsetDateTime::parse(time, &format_description::well_known::Rfc3339).unwrap(),
+            mode: Some("tube".to_string()),
+            previous: statuses(previous),
+            current: statuses(current),
+        }
+    }
+
+    #[test]
+    fn alerts_when_a_line_gets_bad_enough() {
+        let subscription = subscription(LineState::SevereDelays);
+        let transition = |previous, current| transition("2026-10-21T07:30:00Z", previous, current);
+        assert!(should_alert(
+            &subscription,
+            &transition(LineState::GoodService, LineState::SevereDelays)
+        ));
+        assert!(should_alert(
+            &subscription,
+            &transition(LineState::SevereDelays, LineState::Suspended)
+        ));
+        assert!(!should_alert(
+            &subscription,
+            &transition(LineState::GoodService, LineState::MinorDelays)
+        ));
+        assert!(!should_alert(
+            &subscription,
+            &transition(LineState::Suspended, LineState::SevereDelays)
+        ));
+    }
+
+    #[test]
+    fn doesnt_alert_when_a_line_closes_for_the_night() {
+        let subscription = subscription(LineState::SevereDelays);
+        let closing = transition(
+            "2026-10-21T23:45:00Z",
+            LineState::GoodService,
+            LineState::ServiceClosed,
+        );
+        assert!(!should_alert(&subscription, &closing));
+        let closing = transition(
+            "2026-10-21T23:45:00Z",
+            LineState::SevereDelays,
+            LineState::ServiceClosed,
+        );
+        assert!(!should_alert(&subscription, &closing));
+    }
+
+    #[test]
+    fn alerts_when_a_line_reopens_with_severe_delays() {
+        let subscription = subscription(LineState::SevereDelays);
+        let reopening = transition(
+            "2026-10-22T04:30:00Z",
+            LineState::ServiceClosed,
+            LineState::SevereDelays,
+        );
+        assert!(should_alert(&subscription, &reopening));
+        let reopening = transition(
+            "2026-10-22T04:30:00Z",
+            LineState::ServiceClosed,
+            LineState::GoodService,
+        );
+        assert!(!should_alert(&subscription, &reopening));
+    }
+}
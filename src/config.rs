@@ -1,4 +1,10 @@
-use serde::Deserialize;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Deserializer};
+use time::{Time, Weekday};
+
+use crate::localtime;
+use crate::types::{LineState, StationState};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -7,4 +13,438 @@ pub struct Config {
 
     #[serde(default)]
     pub tfl_api_key: Option<String>,
+
+    #[serde(default)]
+    pub tfl: TflConfig,
+
+    /// The live status is reported as stale if the last successful poll was longer ago than this.
+    #[serde(default = "default_status_stale_after_secs")]
+    pub status_stale_after_secs: u64,
+
+    #[serde(default)]
+    pub store: StoreConfig,
+
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+
+    /// The SMTP relay used for email alerts and digests, which are disabled if it's unset.
+    #[serde(default)]
+    pub email: Option<EmailConfig>,
+
+    /// Web Push notifications to browsers, which are disabled if it's unset.
+    #[serde(default)]
+    pub push: Option<PushConfig>,
+
+    /// Bearer token required by the admin endpoints, which are disabled if it's unset.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+fn default_status_stale_after_secs() -> u64 {
+    300
+}
+
+/// Which storage backend to use, configured with e.g.
+/// `store = { backend = "postgres", url = "postgres://localhost/severe_delays" }`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StoreConfig {
+    Sqlite(SqliteConfig),
+    /// Keeps everything in memory, which is useful for tests and ephemeral deployments.
+    Memory,
+    Postgres(PostgresConfig),
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig::Sqlite(SqliteConfig::default())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SqliteConfig {
+    /// Path to the database file. It and its parent directory are created if they don't exist.
+    pub path: PathBuf,
+    pub max_connections: u32,
+    pub busy_timeout_ms: u64,
+    pub journal_mode: SqliteJournalMode,
+    pub synchronous: SqliteSynchronous,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        SqliteConfig {
+            path: PathBuf::from("store/store.db"),
+            max_connections: 5,
+            busy_timeout_ms: 5000,
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteJournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteSynchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostgresConfig {
+    pub url: String,
+    #[serde(default = "default_postgres_max_connections")]
+    pub max_connections: u32,
+}
+
+fn default_postgres_max_connections() -> u32 {
+    5
+}
+
+/// Settings for polling TfL, configured with e.g. `tfl = { modes = ["tube", "tram"] }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TflConfig {
+    /// The transport modes whose lines and stations are recorded, as listed by TfL's
+    /// `/Line/Meta/Modes`, like `tube`, `tram`, `national-rail`, `river-bus`, `cable-car` or
+    /// `bus`.
+    pub modes: Vec<String>,
+    /// Where the TfL API is served from, which can be a local stand-in or a caching proxy.
+    pub base_url: String,
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    pub user_agent: String,
+    /// How many times to try each request, when it fails in a way that might not happen again.
+    pub max_attempts: u32,
+    /// Delay before the first retry, which doubles after each failed attempt up to the maximum.
+    /// A random amount of up to half of it is taken off, so retries aren't all sent at once.
+    pub initial_backoff_ms: u64,
+    /// The longest that a request will wait before retrying. If TfL asks us to wait longer than
+    /// this, the poll fails instead and polling is paused until then.
+    pub max_backoff_ms: u64,
+    /// How many polls in a row can fail before polling is paused.
+    pub breaker_threshold: u32,
+    /// How long polling is paused for, before trying a single poll to see if TfL has recovered.
+    pub breaker_cooldown_secs: u64,
+    pub polling: PollingConfig,
+    /// Successful polls further apart than this leave a gap in the history where nothing is
+    /// known, so it should be longer than the longest polling interval.
+    pub gap_after_secs: u64,
+}
+
+impl Default for TflConfig {
+    fn default() -> Self {
+        TflConfig {
+            modes: ["tube", "dlr", "overground", "elizabeth-line"]
+                .map(String::from)
+                .to_vec(),
+            base_url: "https://api.tfl.gov.uk".to_string(),
+            timeout_secs: 30,
+            connect_timeout_secs: 10,
+            user_agent: concat!("severe-delays/", env!("CARGO_PKG_VERSION")).to_string(),
+            max_attempts: 3,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+            breaker_threshold: 5,
+            breaker_cooldown_secs: 300,
+            polling: PollingConfig::default(),
+            gap_after_secs: 600,
+        }
+    }
+}
+
+/// How often the line and station status are polled, configured with e.g.
+/// `polling = { adaptive = true, stations = { interval_secs = 120 } }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PollingConfig {
+    pub lines: PollTargetConfig,
+    pub stations: PollTargetConfig,
+    /// Poll at the busy intervals during peak hours or while any line is in a severe state, and
+    /// at the quiet intervals overnight, when most lines report `ServiceClosed`.
+    pub adaptive: bool,
+    /// When the peaks are, in London time, like `07:00-10:00`.
+    pub peak_hours: Vec<TimeOfDayRange>,
+    #[serde(deserialize_with = "deserialize_weekdays")]
+    pub peak_days: Vec<Weekday>,
+    /// The states which make it worth polling more often while any line is in them.
+    pub severe_states: Vec<LineState>,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig {
+            lines: PollTargetConfig::default(),
+            stations: PollTargetConfig::default(),
+            adaptive: false,
+            peak_hours: vec![
+                TimeOfDayRange::new(
+                    Time::from_hms(7, 0, 0).unwrap(),
+                    Time::from_hms(10, 0, 0).unwrap(),
+                ),
+                TimeOfDayRange::new(
+                    Time::from_hms(16, 0, 0).unwrap(),
+                    Time::from_hms(19, 0, 0).unwrap(),
+                ),
+            ],
+            peak_days: vec![
+                Weekday::Monday,
+                Weekday::Tuesday,
+                Weekday::Wednesday,
+                Weekday::Thursday,
+                Weekday::Friday,
+            ],
+            severe_states: vec![
+                LineState::Closed,
+                LineState::Suspended,
+                LineState::PartSuspended,
+                LineState::SevereDelays,
+            ],
+        }
+    }
+}
+
+/// The intervals between polls of one kind of status. Only `interval_secs` is used unless
+/// polling is adaptive.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PollTargetConfig {
+    pub interval_secs: u64,
+    pub busy_interval_secs: u64,
+    pub quiet_interval_secs: u64,
+}
+
+impl Default for PollTargetConfig {
+    fn default() -> Self {
+        PollTargetConfig {
+            interval_secs: 60,
+            busy_interval_secs: 30,
+            quiet_interval_secs: 300,
+        }
+    }
+}
+
+/// A daily period in London time, written like `07:00-10:00`, which can run past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDayRange {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl TimeOfDayRange {
+    pub fn new(start: Time, end: Time) -> Self {
+        TimeOfDayRange { start, end }
+    }
+
+    pub fn contains(&self, time: Time) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDayRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value
+            .split_once('-')
+            .and_then(|(start, end)| {
+                Some(TimeOfDayRange::new(
+                    localtime::parse_time_of_day(start.trim())?,
+                    localtime::parse_time_of_day(end.trim())?,
+                ))
+            })
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid time range: {}", value)))
+    }
+}
+
+fn deserialize_weekdays<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Weekday>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| {
+            localtime::parse_weekday(value)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid day: {}", value)))
+        })
+        .collect()
+}
+
+/// Settings for the background job which prunes and compacts the history tables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+    /// How often maintenance runs, which has to be at least an hour.
+    #[serde(deserialize_with = "deserialize_interval_hours")]
+    pub interval_hours: u64,
+    /// Rows which ended more than this many days ago are deleted. Keeps everything if unset.
+    pub retention_days: Option<u64>,
+    /// Merge adjacent rows whose parsed status is identical.
+    pub merge_identical: bool,
+    /// Strip the raw TfL data in closed rows down to the fields that we use.
+    pub strip_raw_data: bool,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            enabled: false,
+            interval_hours: 24,
+            retention_days: None,
+            merge_identical: true,
+            strip_raw_data: false,
+        }
+    }
+}
+
+fn deserialize_interval_hours<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match u64::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom(
+            "maintenance interval_hours must be at least 1",
+        )),
+        hours => Ok(hours),
+    }
+}
+
+/// Settings for notifying webhooks when a line or station's state changes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Webhooks to notify in addition to those registered through the API.
+    pub hooks: Vec<WebhookTarget>,
+    /// How many times to try each delivery before recording it as failed.
+    pub max_attempts: u32,
+    /// Delay before the first retry, which doubles after each failed attempt.
+    pub initial_backoff_ms: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            hooks: vec![],
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// A URL to notify of state changes, and which changes it's interested in. If any lines, modes or
+/// stations are listed, only changes to those are sent, otherwise everything is.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Key for the HMAC-SHA256 signature of each request body.
+    pub secret: String,
+    #[serde(default)]
+    pub lines: Vec<String>,
+    #[serde(default)]
+    pub modes: Vec<String>,
+    #[serde(default)]
+    pub stations: Vec<String>,
+    /// Only send line changes where the old or new status is at least this severe.
+    #[serde(default)]
+    pub min_line_severity: Option<LineState>,
+    /// Only send station changes where the old or new status is at least this severe.
+    #[serde(default)]
+    pub min_station_severity: Option<StationState>,
+}
+
+/// Settings for sending email through an SMTP relay, configured with e.g.
+/// `email = { host = "smtp.example.com", from = "Severe Delays <alerts@example.com>" }`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailConfig {
+    pub host: String,
+    /// Defaults to the standard port for the TLS mode.
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// The sender address, optionally with a display name.
+    pub from: String,
+    #[serde(default = "default_email_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Whether to send each subscriber a daily summary of the previous day's disruptions.
+    #[serde(default = "default_digest_enabled")]
+    pub digest_enabled: bool,
+    /// When the digest is sent, in London time, like `07:00`.
+    #[serde(
+        default = "default_digest_time",
+        deserialize_with = "deserialize_time_of_day"
+    )]
+    pub digest_time: Time,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, which should only be used with a relay on the local machine or network.
+    None,
+    #[default]
+    StartTls,
+    Tls,
+}
+
+fn default_email_timeout_secs() -> u64 {
+    30
+}
+
+fn default_digest_enabled() -> bool {
+    true
+}
+
+fn default_digest_time() -> Time {
+    Time::from_hms(7, 0, 0).unwrap()
+}
+
+fn deserialize_time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Time, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    localtime::parse_time_of_day(&value)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid time of day: {}", value)))
+}
+
+/// Settings for sending Web Push notifications. The VAPID key pair that identifies this server to
+/// the push services is generated on first use and kept in the store.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushConfig {
+    /// How the push services can contact the operator, like `mailto:admin@example.com`.
+    pub contact: String,
+    /// How long the push services should keep trying to deliver to a browser that's offline.
+    #[serde(default = "default_push_ttl_secs")]
+    pub ttl_secs: u64,
+    #[serde(default = "default_push_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_push_ttl_secs() -> u64 {
+    60 * 60
+}
+
+fn default_push_timeout_secs() -> u64 {
+    10
 }
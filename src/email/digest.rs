@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use log::{debug, info, warn};
+use rocket::tokio;
+use time::ext::NumericalDuration;
+use time::{Duration, OffsetDateTime, Time};
+
+use super::{describe_statuses, Mailer};
+use crate::localtime;
+use crate::store::{ConnectionError, GetStatusError, LineHistoryFilter, Store, SubscriptionError};
+use crate::types::{LineState, LineStatusHistoryEntry, NotificationTarget, Subscription};
+
+/// Emails each subscriber a daily summary of the disruptions to their lines over the previous day.
+pub struct Digests {
+    mailer: Mailer,
+    store: Store,
+    send_at: Time,
+}
+
+/// The lines that an email address is interested in, combined from all of its subscriptions.
+struct Subscriber {
+    /// Every line if `None`.
+    lines: Option<Vec<String>>,
+    min_severity: LineState,
+}
+
+#[derive(Debug)]
+enum RunError {
+    Connection(ConnectionError),
+    Subscription(SubscriptionError),
+    GetStatus(GetStatusError),
+}
+
+impl From<ConnectionError> for RunError {
+    fn from(value: ConnectionError) -> Self {
+        RunError::Connection(value)
+    }
+}
+
+impl From<SubscriptionError> for RunError {
+    fn from(value: SubscriptionError) -> Self {
+        RunError::Subscription(value)
+    }
+}
+
+impl From<GetStatusError> for RunError {
+    fn from(value: GetStatusError) -> Self {
+        RunError::GetStatus(value)
+    }
+}
+
+impl Digests {
+    pub fn new(mailer: Mailer, store: Store, send_at: Time) -> Self {
+        Digests {
+            mailer,
+            store,
+            send_at,
+        }
+    }
+
+    /// Sends the digests at the configured time each day. Digests which were due while the server
+    /// wasn't running aren't sent.
+    pub async fn start(self) {
+        loop {
+            let now = OffsetDateTime::now_utc();
+            let next = next_occurrence(now, self.send_at);
+            tokio::time::sleep((next - now).try_into().unwrap_or_default()).await;
+            match self.run(next - 1.days(), next).await {
+                Ok(()) => debug!("Finished sending email digests"),
+                Err(RunError::Connection(err)) => {
+                    warn!("Failed to acquire DB connection for email digests: {}", err)
+                }
+                Err(RunError::Subscription(err)) => {
+                    warn!("Failed to load subscriptions for email digests: {}", err)
+                }
+                Err(RunError::GetStatus(err)) => {
+                    warn!("Failed to load history for email digests: {}", err)
+                }
+            }
+        }
+    }
+
+    async fn run(&self, start: OffsetDateTime, end: OffsetDateTime) -> Result<(), RunError> {
+        let mut connection = self.store.get_connection().await?;
+        let subscribers = group_subscribers(
+            connection
+                .get_subscriptions()
+                .await?
+                .into_iter()
+                .map(|stored| stored.subscription),
+        );
+        let Some(min_severity) = subscribers.values().map(|s| s.min_severity).max() else {
+            return Ok(());
+        };
+        let filter = LineHistoryFilter {
+            min_severity: Some(min_severity),
+            ..Default::default()
+        };
+        let history = connection
+            .get_line_status_history(start, end, &filter)
+            .await?;
+        drop(connection);
+
+        let subject = format!(
+            "Disruption summary for {}",
+            localtime::format_date(localtime::to_london(start).date())
+        );
+        for (address, subscriber) in &subscribers {
+            let body = digest_body(subscriber, &history, start, end);
+            match self.mailer.send(address, &subject, body).await {
+                Ok(()) => info!("Sent email digest to {}", address),
+                Err(err) => warn!("Failed to send email digest to {}: {}", address, err),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn group_subscribers(
+    subscriptions: impl Iterator<Item = Subscription>,
+) -> HashMap<String, Subscriber> {
+    let mut subscribers: HashMap<String, Subscriber> = HashMap::new();
+    for subscription in subscriptions {
+        let NotificationTarget::Email { address } = subscription.target else {
+            continue;
+        };
+        let lines = (!subscription.lines.is_empty()).then_some(subscription.lines);
+        match subscribers.get_mut(&address) {
+            Some(subscriber) => {
+                match (&mut subscriber.lines, lines) {
+                    (Some(existing), Some(lines)) => existing.extend(lines),
+                    (existing, _) => *existing = None,
+                }
+                subscriber.min_severity = subscriber.min_severity.max(subscription.min_severity);
+            }
+            None => {
+                subscribers.insert(
+                    address,
+                    Subscriber {
+                        lines,
+                        min_severity: subscription.min_severity,
+                    },
+                );
+            }
+        }
+    }
+    subscribers
+}
+
+/// Lists each period during which the subscriber's lines were at least as disrupted as they asked
+/// to be alerted about, clipped to the digest's time range. Lines being closed overnight aren't
+/// disruptions, so aren't listed.
+fn digest_body(
+    subscriber: &Subscriber,
+    history: &HashMap<String, Vec<LineStatusHistoryEntry>>,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+) -> String {
+    let lines: BTreeMap<_, _> = history
+        .iter()
+        .filter(|(line, _)| {
+            subscriber
+                .lines
+                .as_ref()
+                .is_none_or(|lines| lines.contains(line))
+        })
+        .filter_map(|(line, entries)| {
+            let entries: Vec<_> = entries
+                .iter()
+                .filter(|entry| {
+                    entry.statuses.iter().any(|status| {
+                        status
+                            .status
+                            .is_disruption_at_least(subscriber.min_severity)
+                    })
+                })
+                .collect();
+            (!entries.is_empty()).then_some((line, entries))
+        })
+        .collect();
+
+    let mut body = String::new();
+    if lines.is_empty() {
+        writeln!(
+            body,
+            "There were no disruptions on your lines between {} and {}.",
+            localtime::describe(start),
+            localtime::describe(end)
+        )
+        .unwrap();
+        return body;
+    }
+    writeln!(
+        body,
+        "Disruptions on your lines between {} and {}:",
+        localtime::describe(start),
+        localtime::describe(end)
+    )
+    .unwrap();
+    for (line, entries) in lines {
+        let periods: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let period_start = entry.start_time.max(start);
+                let period_end = entry.end_time.unwrap_or(end).min(end);
+                (entry, period_start, period_end)
+            })
+            .collect();
+        let total: Duration = periods
+            .iter()
+            .map(|(_, period_start, period_end)| *period_end - *period_start)
+            .sum();
+        writeln!(
+            body,
+            "\n{} ({}), disrupted for {}:",
+            line,
+            entries[0].mode,
+            format_duration(total)
+        )
+        .unwrap();
+        for (entry, period_start, period_end) in periods {
+            let from = localtime::format_time_of_day(localtime::to_london(period_start).time());
+            let duration = format_duration(period_end - period_start);
+            let period = if entry.end_time.is_none_or(|end_time| end_time > end) {
+                format!("{} onwards ({} so far)", from, duration)
+            } else {
+                let until = localtime::format_time_of_day(localtime::to_london(period_end).time());
+                format!("{} to {} ({})", from, until, duration)
+            };
+            writeln!(
+                body,
+                "\n{}:\n{}",
+                period,
+                describe_statuses(&entry.statuses).trim_end()
+            )
+            .unwrap();
+        }
+    }
+    body
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.whole_minutes();
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h {}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// The next time after `now` that it's `time` in London.
+fn next_occurrence(now: OffsetDateTime, time: Time) -> OffsetDateTime {
+    let mut date = localtime::to_london(now).date();
+    loop {
+        let candidate = localtime::from_london(date.with_time(time));
+        if candidate > now {
+            return candidate;
+        }
+        date = date.next_day().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::format_description;
+
+    use super::*;
+    use crate::types::LineStatus;
+
+    fn at(time: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(time, &format_description::well_known::Rfc3339).unwrap()
+    }
+
+    fn entry(start: &str, end: &str, state: LineState) -> LineStatusHistoryEntry {
+        LineStatusHistoryEntry {
+            start_time: at(start),
+            end_time: Some(at(end)),
+            mode: "tube".to_string(),
+            statuses: vec![LineStatus {
+                status: state,
+                reason: None,
+                validity_periods: vec![],
+                disruption: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn leaves_out_lines_closing_for_the_night() {
+        let subscriber = Subscriber {
+            lines: Some(vec!["central".to_string()]),
+            min_severity: LineState::SevereDelays,
+        };
+        let start = at("2026-10-20T23:00:00Z");
+        let end = at("2026-10-21T23:00:00Z");
+        let mut history = HashMap::new();
+        history.insert(
+            "central".to_string(),
+            vec![entry(
+                "2026-10-20T23:30:00Z",
+                "2026-10-21T04:30:00Z",
+                LineState::ServiceClosed,
+            )],
+        );
+        let body = digest_body(&subscriber, &history, start, end);
+        assert!(body.starts_with("There were no disruptions"), "{}", body);
+
+        history.get_mut("central").unwrap().push(entry(
+            "2026-10-21T07:00:00Z",
+            "2026-10-21T07:45:00Z",
+            LineState::SevereDelays,
+        ));
+        let body = digest_body(&subscriber, &history, start, end);
+        assert!(
+            body.contains("central (tube), disrupted for 45m"),
+            "{}",
+            body
+        );
+        assert!(!body.contains("Service Closed"), "{}", body);
+    }
+}
@@ -0,0 +1,59 @@
+use rocket::fairing::{Fairing, Info, Kind, Result};
+use rocket::tokio::spawn;
+use rocket::{Build, Orbit, Rocket};
+
+use super::digest::Digests;
+use super::Mailer;
+use crate::config::Config;
+use crate::store::Store;
+
+/// Makes a `Mailer` available if email is configured, and starts the daily digest task.
+pub struct EmailFairing;
+
+impl EmailFairing {
+    pub fn new() -> Self {
+        EmailFairing
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for EmailFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Email",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> Result {
+        let config = rocket.state::<Config>().unwrap();
+        let Some(email) = &config.email else {
+            return Ok(rocket);
+        };
+        match Mailer::new(email) {
+            Ok(mailer) => Ok(rocket.manage(mailer)),
+            Err(e) => {
+                log::error!("Failed to initialize email: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.state::<Config>().unwrap();
+        let (Some(email), Some(mailer)) = (&config.email, rocket.state::<Mailer>()) else {
+            return;
+        };
+        if !email.digest_enabled {
+            return;
+        }
+        let digests = Digests::new(
+            mailer.clone(),
+            rocket.state::<Store>().unwrap().clone(),
+            email.digest_time,
+        );
+        spawn(async move {
+            digests.start().await;
+        });
+    }
+}
@@ -0,0 +1,112 @@
+mod digest;
+mod fairing;
+
+use std::fmt;
+use std::time::Duration;
+
+use lettre::address::AddressError;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::{EmailConfig, SmtpTls};
+use crate::types::LineStatus;
+
+pub use fairing::EmailFairing;
+
+/// Sends plain text emails through the configured SMTP relay.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[derive(Debug)]
+pub enum EmailError {
+    Address(AddressError),
+    Message(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+}
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmailError::Address(err) => write!(f, "invalid address: {}", err),
+            EmailError::Message(err) => write!(f, "failed to build message: {}", err),
+            EmailError::Smtp(err) => write!(f, "SMTP error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+impl From<AddressError> for EmailError {
+    fn from(value: AddressError) -> Self {
+        EmailError::Address(value)
+    }
+}
+
+impl From<lettre::error::Error> for EmailError {
+    fn from(value: lettre::error::Error) -> Self {
+        EmailError::Message(value)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for EmailError {
+    fn from(value: lettre::transport::smtp::Error) -> Self {
+        EmailError::Smtp(value)
+    }
+}
+
+impl Mailer {
+    pub fn new(config: &EmailConfig) -> Result<Self, EmailError> {
+        let builder = match config.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        };
+        let mut builder = builder.timeout(Some(Duration::from_secs(config.timeout_secs)));
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &config.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                config.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(Mailer {
+            transport: builder.build(),
+            from: config.from.parse()?,
+        })
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), EmailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Whether `address` is something that we could send an email to.
+pub fn is_valid_address(address: &str) -> bool {
+    address.parse::<Mailbox>().is_ok()
+}
+
+/// Lists each status on its own line, with its reason if it has one.
+pub fn describe_statuses(statuses: &[LineStatus]) -> String {
+    statuses
+        .iter()
+        .map(|status| match &status.reason {
+            Some(reason) => format!("- {}: {}\n", status.status.description(), reason),
+            None => format!("- {}\n", status.status.description()),
+        })
+        .collect()
+}
@@ -0,0 +1,57 @@
+use rocket::tokio::sync::broadcast;
+
+use crate::types::{LineTransition, StationTransition};
+
+/// How many events can be buffered for a slow subscriber before it starts missing them.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum StatusEvent {
+    Line(LineTransition),
+    Station(StationTransition),
+}
+
+impl StatusEvent {
+    /// Whether the event changes the state of a line or station, rather than just its
+    /// description. Lines that we didn't know about before aren't counted as changes.
+    pub fn is_state_change(&self) -> bool {
+        match self {
+            StatusEvent::Line(LineTransition {
+                previous: Some(previous),
+                current: Some(current),
+                ..
+            }) => !previous
+                .iter()
+                .map(|s| s.status)
+                .eq(current.iter().map(|s| s.status)),
+            StatusEvent::Line(_) => false,
+            StatusEvent::Station(transition) => !transition
+                .previous
+                .iter()
+                .map(|s| s.status)
+                .eq(transition.current.iter().map(|s| s.status)),
+        }
+    }
+}
+
+/// Broadcasts status changes recorded by the poller to anyone who is listening.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<StatusEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        EventBus { sender }
+    }
+
+    pub fn publish(&self, event: StatusEvent) {
+        // Sending only fails if nobody is subscribed, in which case there's nothing to do
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StatusEvent> {
+        self.sender.subscribe()
+    }
+}
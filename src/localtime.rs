@@ -0,0 +1,80 @@
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday};
+
+/// Converts a time to London local time. The UK observes British Summer Time (UTC+1) from 01:00 UTC
+/// on the last Sunday in March until 01:00 UTC on the last Sunday in October, and GMT otherwise.
+pub fn to_london(time: OffsetDateTime) -> OffsetDateTime {
+    let year = time.to_offset(UtcOffset::UTC).year();
+    let bst_start = last_sunday(year, Month::March)
+        .with_time(Time::from_hms(1, 0, 0).unwrap())
+        .assume_utc();
+    let bst_end = last_sunday(year, Month::October)
+        .with_time(Time::from_hms(1, 0, 0).unwrap())
+        .assume_utc();
+    if time >= bst_start && time < bst_end {
+        time.to_offset(UtcOffset::from_hms(1, 0, 0).unwrap())
+    } else {
+        time.to_offset(UtcOffset::UTC)
+    }
+}
+
+/// Converts a London local time to an absolute time. Times skipped when the clocks go forward are
+/// treated as GMT, and times repeated when they go back are treated as BST.
+pub fn from_london(local: PrimitiveDateTime) -> OffsetDateTime {
+    let summer = local.assume_offset(UtcOffset::from_hms(1, 0, 0).unwrap());
+    if to_london(summer).offset() == summer.offset() {
+        summer
+    } else {
+        local.assume_utc()
+    }
+}
+
+fn last_sunday(year: i32, month: Month) -> Date {
+    let next_month_year = if month == Month::December {
+        year + 1
+    } else {
+        year
+    };
+    Date::from_calendar_date(next_month_year, month.next(), 1)
+        .unwrap()
+        .prev_occurrence(Weekday::Sunday)
+}
+
+/// Parses a 24 hour time of day, like `07:30`.
+pub fn parse_time_of_day(value: &str) -> Option<Time> {
+    let (hours, minutes) = value.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    Time::from_hms(hours.parse().ok()?, minutes.parse().ok()?, 0).ok()
+}
+
+pub fn format_time_of_day(time: Time) -> String {
+    format!("{:02}:{:02}", time.hour(), time.minute())
+}
+
+/// Formats a date like `Monday 19 October`.
+pub fn format_date(date: Date) -> String {
+    format!("{} {} {}", date.weekday(), date.day(), date.month())
+}
+
+/// Describes a time in London time, like `07:30 on Monday 19 October`.
+pub fn describe(time: OffsetDateTime) -> String {
+    let local = to_london(time);
+    format!(
+        "{} on {}",
+        format_time_of_day(local.time()),
+        format_date(local.date())
+    )
+}
+
+/// Parses a weekday from its English name, ignoring case.
+pub fn parse_weekday(value: &str) -> Option<Weekday> {
+    let mut weekday = Weekday::Monday;
+    for _ in 0..7 {
+        if weekday.to_string().eq_ignore_ascii_case(value) {
+            return Some(weekday);
+        }
+        weekday = weekday.next();
+    }
+    None
+}
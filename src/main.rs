@@ -1,15 +1,30 @@
+mod alerts;
 mod config;
 mod cors;
+mod email;
+mod events;
+mod localtime;
+mod maintenance;
+mod push;
 mod routes;
 mod store;
+#[cfg(test)]
+mod tests;
 mod tfl;
 mod types;
+mod webhooks;
 
+use alerts::AlertFairing;
 use config::Config;
 use cors::CorsFairing;
+use email::EmailFairing;
+use events::EventBus;
+use maintenance::MaintenanceFairing;
+use push::PushFairing;
 use rocket::fairing::AdHoc;
 use store::StoreFairing;
 use tfl::TflFairing;
+use webhooks::WebhookFairing;
 
 #[macro_use]
 extern crate rocket;
@@ -18,10 +33,21 @@ extern crate rocket;
 async fn rocket() -> _ {
     rocket::build()
         .attach(AdHoc::config::<Config>())
+        .manage(EventBus::new())
         .attach(StoreFairing::new())
         .attach(CorsFairing)
         .attach(TflFairing::new())
+        .attach(MaintenanceFairing::new())
+        .attach(WebhookFairing::new())
+        .attach(EmailFairing::new())
+        .attach(AlertFairing::new())
+        .attach(PushFairing::new())
         .mount("/", routes::utils::get_routes())
         .mount("/", routes::fe::get_routes())
+        .mount("/", routes::feeds::get_routes())
         .mount("/api", routes::api::get_routes())
+        .mount("/api", routes::ws::get_routes())
+        .mount("/api", routes::webhooks::get_routes())
+        .mount("/api", routes::subscriptions::get_routes())
+        .mount("/api", routes::push::get_routes())
 }
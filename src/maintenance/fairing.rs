@@ -0,0 +1,37 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::spawn;
+use rocket::{Orbit, Rocket};
+
+use super::Maintenance;
+use crate::config::Config;
+use crate::store::Store;
+
+pub struct MaintenanceFairing;
+
+impl MaintenanceFairing {
+    pub fn new() -> Self {
+        MaintenanceFairing
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for MaintenanceFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "History Maintenance Task",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.state::<Config>().unwrap();
+        if !config.maintenance.enabled {
+            return;
+        }
+        let maintenance = Maintenance::new(config.maintenance.clone());
+        let store = rocket.state::<Store>().unwrap().clone();
+        spawn(async move {
+            maintenance.start(store).await;
+        });
+    }
+}
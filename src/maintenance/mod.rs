@@ -0,0 +1,118 @@
+mod fairing;
+
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rocket::tokio;
+use serde_json::Value;
+use time::ext::NumericalDuration;
+use time::OffsetDateTime;
+
+use crate::config::MaintenanceConfig;
+use crate::store::{ConnectionError, MaintenanceError, Store};
+use crate::tfl;
+
+pub use fairing::MaintenanceFairing;
+
+/// Periodically prunes and compacts the history tables so that they don't grow forever.
+pub struct Maintenance {
+    config: MaintenanceConfig,
+}
+
+impl Maintenance {
+    pub fn new(config: MaintenanceConfig) -> Self {
+        Maintenance { config }
+    }
+
+    pub async fn start(self, store: Store) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.interval_hours * 60 * 60));
+        loop {
+            interval.tick().await;
+            match self.run(&store).await {
+                Ok(()) => debug!("Finished history maintenance"),
+                Err(RunError::Connection(err)) => warn!(
+                    "Failed to acquire DB connection for history maintenance: {:?}",
+                    err
+                ),
+                Err(RunError::Maintenance(err)) => {
+                    warn!("Error running history maintenance: {:?}", err)
+                }
+            }
+        }
+    }
+
+    async fn run(&self, store: &Store) -> Result<(), RunError> {
+        let mut connection = store.get_connection().await?;
+
+        if let Some(retention_days) = self.config.retention_days {
+            let cutoff = OffsetDateTime::now_utc() - (retention_days as i64).days();
+            let deleted = connection.purge_history(cutoff).await?;
+            info!(
+                "Deleted {} history rows older than {} days",
+                deleted, retention_days
+            );
+        }
+
+        if self.config.merge_identical {
+            let merged = connection.merge_line_history(&same_line_status).await?
+                + connection
+                    .merge_station_history(&same_station_status)
+                    .await?;
+            info!("Merged {} history rows with identical status", merged);
+        }
+
+        if self.config.strip_raw_data {
+            let stripped = connection
+                .strip_line_history(&tfl::strip_line_status)
+                .await?
+                + connection
+                    .strip_station_history(&tfl::strip_station_status)
+                    .await?;
+            info!("Stripped unused fields from {} history rows", stripped);
+        }
+
+        connection.vacuum().await?;
+        Ok(())
+    }
+}
+
+fn same_line_status(line: &str, old: &Value, new: &Value) -> bool {
+    match (
+        tfl::try_parse_line_status(line, old),
+        tfl::try_parse_line_status(line, new),
+    ) {
+        (Some((old_metadata, old)), Some((new_metadata, new))) => {
+            old_metadata.mode == new_metadata.mode && old == new
+        }
+        _ => false,
+    }
+}
+
+fn same_station_status(station: &str, old: &[Value], new: &[Value]) -> bool {
+    match (
+        tfl::try_parse_station_status(station, old),
+        tfl::try_parse_station_status(station, new),
+    ) {
+        (Some(old), Some(new)) => old == new,
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+enum RunError {
+    Connection(ConnectionError),
+    Maintenance(MaintenanceError),
+}
+
+impl From<ConnectionError> for RunError {
+    fn from(err: ConnectionError) -> Self {
+        RunError::Connection(err)
+    }
+}
+
+impl From<MaintenanceError> for RunError {
+    fn from(err: MaintenanceError) -> Self {
+        RunError::Maintenance(err)
+    }
+}
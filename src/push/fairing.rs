@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use rocket::fairing::{Fairing, Info, Kind, Result};
+use rocket::tokio::spawn;
+use rocket::{Build, Orbit, Rocket};
+
+use super::Push;
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::store::Store;
+use crate::tfl::LoadedStationDetails;
+
+/// Manages an `Option<Arc<Push>>`, which is `None` if Web Push isn't configured, and starts
+/// sending notifications.
+pub struct PushFairing;
+
+impl PushFairing {
+    pub fn new() -> Self {
+        PushFairing
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for PushFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Web Push",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> Result {
+        let config = rocket.state::<Config>().unwrap();
+        let Some(push) = &config.push else {
+            return Ok(rocket.manage(None::<Arc<Push>>));
+        };
+        let push = Push::new(
+            push.clone(),
+            rocket.state::<Store>().unwrap().clone(),
+            rocket.state::<Arc<LoadedStationDetails>>().unwrap().clone(),
+        )
+        .await;
+        match push {
+            Ok(push) => Ok(rocket.manage(Some(Arc::new(push)))),
+            Err(e) => {
+                log::error!("Failed to initialize Web Push: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(Some(push)) = rocket.state::<Option<Arc<Push>>>() else {
+            return;
+        };
+        let push = push.clone();
+        let events = rocket.state::<EventBus>().unwrap().clone();
+        spawn(async move {
+            push.start(events).await;
+        });
+    }
+}
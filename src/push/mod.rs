@@ -0,0 +1,365 @@
+mod fairing;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use log::{debug, warn};
+use rocket::tokio::{self, sync::broadcast::error::RecvError};
+use serde::Serialize;
+use web_push_native::jwt_simple::algorithms::{ECDSAP256PublicKeyLike, ES256KeyPair};
+use web_push_native::p256::PublicKey;
+use web_push_native::{Auth, WebPushBuilder};
+
+use crate::config::PushConfig;
+use crate::events::{EventBus, StatusEvent};
+use crate::store::{ConnectionError, PushError, Store};
+use crate::tfl::LoadedStationDetails;
+use crate::types::{LineTransition, PushSubscription, StationTransition};
+
+pub use fairing::PushFairing;
+
+/// Sends Web Push notifications to the registered browsers when a line or station's state
+/// changes.
+pub struct Push {
+    config: PushConfig,
+    key_pair: ES256KeyPair,
+    client: reqwest::Client,
+    store: Store,
+    station_details: Arc<LoadedStationDetails>,
+}
+
+#[derive(Debug)]
+pub enum PushInitError {
+    Connection(ConnectionError),
+    Store(PushError),
+    Key(web_push_native::jwt_simple::Error),
+}
+
+impl fmt::Display for PushInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushInitError::Connection(err) => write!(f, "failed to acquire DB connection: {}", err),
+            PushInitError::Store(err) => write!(f, "failed to load VAPID keys: {}", err),
+            PushInitError::Key(err) => write!(f, "invalid VAPID key: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PushInitError {}
+
+impl From<ConnectionError> for PushInitError {
+    fn from(value: ConnectionError) -> Self {
+        PushInitError::Connection(value)
+    }
+}
+
+impl From<PushError> for PushInitError {
+    fn from(value: PushError) -> Self {
+        PushInitError::Store(value)
+    }
+}
+
+#[derive(Debug)]
+enum SendError {
+    /// The push service says that the browser has unsubscribed.
+    Gone,
+    Failed(String),
+}
+
+/// The body of each push message, which the frontend's service worker shows as a notification.
+#[derive(Debug, Serialize)]
+struct PushPayload {
+    title: String,
+    body: String,
+    /// Notifications with the same tag replace each other, so only the latest is shown.
+    tag: String,
+    url: &'static str,
+}
+
+impl Push {
+    /// Loads the VAPID key pair from the store, generating one if this is the first run.
+    pub async fn new(
+        config: PushConfig,
+        store: Store,
+        station_details: Arc<LoadedStationDetails>,
+    ) -> Result<Self, PushInitError> {
+        let private_key = store
+            .get_connection()
+            .await?
+            .get_or_insert_vapid_key(&ES256KeyPair::generate().to_bytes())
+            .await?;
+        let key_pair = ES256KeyPair::from_bytes(&private_key).map_err(PushInitError::Key)?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap();
+        Ok(Push {
+            config,
+            key_pair,
+            client,
+            store,
+            station_details,
+        })
+    }
+
+    /// The public half of the VAPID key, in the format expected by `PushManager.subscribe()`.
+    pub fn public_key(&self) -> String {
+        Base64UrlUnpadded::encode_string(
+            &self
+                .key_pair
+                .public_key()
+                .public_key()
+                .to_bytes_uncompressed(),
+        )
+    }
+
+    pub async fn start(self: Arc<Self>, events: EventBus) {
+        let mut receiver = events.subscribe();
+        loop {
+            match receiver.recv().await {
+                Ok(event) => self.dispatch(&event).await,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Push notifications fell behind, skipped {} events", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Starts sending `event` to each browser that's interested in it.
+    async fn dispatch(self: &Arc<Self>, event: &StatusEvent) {
+        if !event.is_state_change() {
+            return;
+        }
+        let subscriptions = match self.store.get_connection().await {
+            Ok(mut connection) => match connection.get_push_subscriptions().await {
+                Ok(subscriptions) => subscriptions,
+                Err(err) => {
+                    warn!("Failed to load push subscriptions: {:?}", err);
+                    return;
+                }
+            },
+            Err(err) => {
+                warn!(
+                    "Failed to acquire DB connection to load push subscriptions: {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        let subscriptions: Vec<_> = subscriptions
+            .into_iter()
+            .filter(|subscription| subscription_matches(subscription, event))
+            .collect();
+        if subscriptions.is_empty() {
+            return;
+        }
+        let payload = match event {
+            StatusEvent::Line(transition) => line_payload(transition),
+            StatusEvent::Station(transition) => self.station_payload(transition).await,
+        };
+        let payload = match serde_json::to_string(&payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Failed to serialize push payload: {:?}", err);
+                return;
+            }
+        };
+        for subscription in subscriptions {
+            let push = self.clone();
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                push.deliver(&subscription, &payload).await;
+            });
+        }
+    }
+
+    /// Sends the payload, and forgets the subscription if the browser is no longer subscribed.
+    async fn deliver(&self, subscription: &PushSubscription, payload: &str) {
+        match self.send(subscription, payload).await {
+            Ok(()) => debug!("Sent push notification to {}", subscription.endpoint),
+            Err(SendError::Gone) => {
+                debug!(
+                    "Removing expired push subscription {}",
+                    subscription.endpoint
+                );
+                let result = match self.store.get_connection().await {
+                    Ok(mut connection) => connection
+                        .delete_push_subscription(&subscription.endpoint)
+                        .await
+                        .map(|_| ())
+                        .map_err(|err| format!("{:?}", err)),
+                    Err(err) => Err(format!("{:?}", err)),
+                };
+                if let Err(err) = result {
+                    warn!(
+                        "Failed to remove expired push subscription {}: {}",
+                        subscription.endpoint, err
+                    );
+                }
+            }
+            Err(SendError::Failed(err)) => warn!(
+                "Failed to send push notification to {}: {}",
+                subscription.endpoint, err
+            ),
+        }
+    }
+
+    async fn send(&self, subscription: &PushSubscription, payload: &str) -> Result<(), SendError> {
+        let (public_key, auth) = parse_keys(&subscription.p256dh, &subscription.auth)
+            .ok_or_else(|| SendError::Failed("Invalid subscription keys".to_string()))?;
+        let endpoint = subscription
+            .endpoint
+            .parse()
+            .map_err(|err| SendError::Failed(format!("Invalid endpoint: {}", err)))?;
+        let request = WebPushBuilder::new(endpoint, public_key, auth)
+            .with_valid_duration(Duration::from_secs(self.config.ttl_secs))
+            .with_vapid(&self.key_pair, &self.config.contact)
+            .build(payload)
+            .map_err(|err| SendError::Failed(err.to_string()))?;
+        let request = reqwest::Request::try_from(request)
+            .map_err(|err| SendError::Failed(err.to_string()))?;
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .map_err(|err| SendError::Failed(err.to_string()))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE => Err(SendError::Gone),
+            status => Err(SendError::Failed(format!("Unexpected status {}", status))),
+        }
+    }
+
+    async fn station_payload(&self, transition: &StationTransition) -> PushPayload {
+        let name = self
+            .station_details
+            .get_details()
+            .await
+            .ok()
+            .and_then(|details| {
+                details
+                    .into_iter()
+                    .find(|details| details.id == transition.station)
+                    .map(|details| details.common_name)
+            })
+            .unwrap_or_else(|| transition.station.clone());
+        let state = match transition.current.iter().map(|status| status.status).min() {
+            Some(state) => state.description(),
+            None => "No disruptions",
+        };
+        PushPayload {
+            title: format!("{}: {}", name, state),
+            body: transition
+                .current
+                .iter()
+                .map(|status| status.description.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            tag: format!("station:{}", transition.station),
+            url: "/",
+        }
+    }
+}
+
+fn line_payload(transition: &LineTransition) -> PushPayload {
+    let current = transition.current.as_deref().unwrap_or_default();
+    let title = match current.iter().map(|status| status.status).min() {
+        Some(state) => format!("{}: {}", transition.line, state.description()),
+        None => transition.line.clone(),
+    };
+    PushPayload {
+        title,
+        body: current
+            .iter()
+            .filter_map(|status| status.reason.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        tag: format!("line:{}", transition.line),
+        url: "/",
+    }
+}
+
+fn subscription_matches(subscription: &PushSubscription, event: &StatusEvent) -> bool {
+    let unfiltered = subscription.lines.is_empty() && subscription.stations.is_empty();
+    match event {
+        StatusEvent::Line(transition) => {
+            let included = unfiltered || subscription.lines.contains(&transition.line);
+            let severe_enough = subscription.min_line_severity.is_none_or(|min_severity| {
+                transition
+                    .previous
+                    .iter()
+                    .chain(transition.current.iter())
+                    .flatten()
+                    .any(|status| status.status.is_disruption_at_least(min_severity))
+            });
+            included && severe_enough
+        }
+        StatusEvent::Station(transition) => {
+            unfiltered || subscription.stations.contains(&transition.station)
+        }
+    }
+}
+
+/// Decodes a browser's public key and authentication secret, which are base64url encoded.
+pub fn parse_keys(p256dh: &str, auth: &str) -> Option<(PublicKey, Auth)> {
+    let decode = |value: &str| Base64UrlUnpadded::decode_vec(value.trim_end_matches('=')).ok();
+    let public_key = PublicKey::from_sec1_bytes(&decode(p256dh)?).ok()?;
+    let auth = decode(auth)?;
+    if auth.len() != 16 {
+        return None;
+    }
+    Some((public_key, Auth::clone_from_slice(&auth)))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::types::{LineState, LineStatus};
+
+    fn line_change(previous: LineState, current: LineState) -> StatusEvent {
+        let statuses = |state| {
+            Some(vec![LineStatus {
+                status: state,
+                reason: None,
+                validity_periods: vec![],
+                disruption: None,
+            }])
+        };
+        StatusEvent::Line(LineTransition {
+            line: "central".to_string(),
+            time: OffsetDateTime::now_utc(),
+            mode: Some("tube".to_string()),
+            previous: statuses(previous),
+            current: statuses(current),
+        })
+    }
+
+    #[test]
+    fn doesnt_push_lines_closing_or_opening_for_the_night() {
+        let subscription = PushSubscription {
+            endpoint: "https://push.example.com/abc".to_string(),
+            p256dh: String::new(),
+            auth: String::new(),
+            lines: vec!["central".to_string()],
+            stations: vec![],
+            min_line_severity: Some(LineState::SevereDelays),
+        };
+        assert!(!subscription_matches(
+            &subscription,
+            &line_change(LineState::GoodService, LineState::ServiceClosed)
+        ));
+        assert!(!subscription_matches(
+            &subscription,
+            &line_change(LineState::ServiceClosed, LineState::GoodService)
+        ));
+        assert!(subscription_matches(
+            &subscription,
+            &line_change(LineState::ServiceClosed, LineState::SevereDelays)
+        ));
+    }
+}
@@ -0,0 +1,34 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use subtle::ConstantTimeEq;
+
+use crate::config::Config;
+
+/// Request guard for the admin endpoints, which require the configured admin token as a bearer
+/// token. If no token is configured then the admin endpoints are disabled.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.rocket().state::<Config>().unwrap();
+        let Some(admin_token) = &config.admin_token else {
+            return Outcome::Error((Status::Forbidden, ()));
+        };
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        // Compared in constant time so that the token can't be guessed from how long it takes
+        let authorized =
+            token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(admin_token.as_bytes())));
+        if authorized {
+            Outcome::Success(Admin)
+        } else {
+            Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
@@ -3,19 +3,36 @@ use std::sync::Arc;
 
 use log::warn;
 use rocket::form::FromFormField;
-use rocket::State;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
 use rocket::{serde::json::Json, Route};
+use rocket::{Shutdown, State};
 use serde::Serialize;
 use time::ext::NumericalDuration;
 use time::{format_description, OffsetDateTime};
 
-use crate::store::StoreConnection;
-use crate::tfl::LoadedStationDetails;
-use crate::types::{LineMetadata, StationState};
-use crate::{tfl, types::LineState};
+use crate::config::Config;
+use crate::events::{EventBus, StatusEvent};
+use crate::store::{GetStatusError, LineHistoryFilter, StationHistoryFilter, StoreConnection};
+use crate::tfl::{BreakerState, LoadedStationDetails, PollTarget, PollerHealth, StoreHealth, Tfl};
+use crate::types::{
+    CollectionGap, CurrentLineStatus, CurrentStatus, Disruption, LineState, LineStatus,
+    LineStatusHistoryEntry, LineTransition, StationState, StationStatus, StationStatusHistoryEntry,
+    StationTransition,
+};
 
 pub fn get_routes() -> Vec<Route> {
-    routes![line_history, station_history, station_details]
+    routes![
+        line_history,
+        station_history,
+        collection_gaps,
+        status,
+        status_at,
+        events,
+        station_details,
+        health
+    ]
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -32,22 +49,27 @@ struct ApiLineStatus {
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-struct ApiLineStatusEntry {
+#[serde(rename_all = "camelCase")]
+pub(super) struct ApiLineStatusEntry {
     status: LineState,
     reason: Option<String>,
+    /// When TfL says that the status applies. Periods which aren't `isNow` have been announced
+    /// for the future, or have already ended.
+    validity_periods: Vec<ApiValidityPeriod>,
+    disruption: Option<Disruption>,
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-struct ApiLineMetadata {
-    mode: Option<String>,
+#[serde(rename_all = "camelCase")]
+pub(super) struct ApiValidityPeriod {
+    from: SerializableDateTime,
+    to: SerializableDateTime,
+    is_now: bool,
 }
 
-impl From<Option<LineMetadata>> for ApiLineMetadata {
-    fn from(value: Option<LineMetadata>) -> Self {
-        ApiLineMetadata {
-            mode: value.map(|m| m.mode),
-        }
-    }
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub(super) struct ApiLineMetadata {
+    mode: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -63,18 +85,117 @@ struct ApiStationStatus {
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-struct ApiStationStatusEntry {
+pub(super) struct ApiStationStatusEntry {
     status: StationState,
     description: String,
 }
 
+/// The periods when nothing is known about the line or station status, because it wasn't being
+/// collected.
+#[derive(Debug, Clone, Serialize)]
+struct ApiCollectionGaps {
+    lines: Vec<ApiCollectionGap>,
+    stations: Vec<ApiCollectionGap>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ApiCollectionGap {
+    from: SerializableDateTime,
+    /// `None` if the gap is still going on.
+    to: Option<SerializableDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ApiStatusAt {
+    time: SerializableDateTime,
+    lines: HashMap<String, ApiLineSnapshot>,
+    stations: HashMap<String, ApiStationStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ApiLineSnapshot {
+    #[serde(flatten)]
+    status: ApiLineStatus,
+    metadata: ApiLineMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ApiCurrentStatus {
+    last_updated: SerializableDateTime,
+    /// Whether the last successful poll of TfL was too long ago to be trusted.
+    stale: bool,
+    lines: HashMap<String, ApiCurrentLineStatus>,
+    stations: HashMap<String, ApiCurrentStationStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct ApiCurrentLineStatus {
+    entries: Vec<ApiLineStatusEntry>,
+    metadata: ApiLineMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct ApiCurrentStationStatus {
+    entries: Vec<ApiStationStatusEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct ApiLineEvent {
+    line: String,
+    time: SerializableDateTime,
+    mode: Option<String>,
+    previous: Option<Vec<ApiLineStatusEntry>>,
+    current: Option<Vec<ApiLineStatusEntry>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct ApiStationEvent {
+    station: String,
+    time: SerializableDateTime,
+    previous: Vec<ApiStationStatusEntry>,
+    current: Vec<ApiStationStatusEntry>,
+}
+
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 struct StationDetails {
     name: String,
 }
 
-#[derive(Debug, Clone)]
-struct SerializableDateTime(OffsetDateTime);
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiHealth {
+    breaker: ApiBreakerState,
+    /// When polling resumes, if it's paused.
+    paused_until: Option<SerializableDateTime>,
+    consecutive_failures: u32,
+    last_success: Option<SerializableDateTime>,
+    last_failure: Option<SerializableDateTime>,
+    last_error: Option<String>,
+    /// How recording the polled status in the store is going, separately from polling TfL.
+    store: ApiStoreHealth,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiStoreHealth {
+    last_success: Option<SerializableDateTime>,
+    last_failure: Option<SerializableDateTime>,
+    last_error: Option<String>,
+}
+
+/// `closed` while polling as normal, `open` while polling is paused after repeated failures, and
+/// `halfOpen` while a single poll checks whether TfL has recovered.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum ApiBreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct SerializableDateTime(OffsetDateTime);
 
 impl Serialize for SerializableDateTime {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -92,7 +213,7 @@ impl Serialize for SerializableDateTime {
 
 impl<'a> FromFormField<'a> for SerializableDateTime {
     fn from_value(field: rocket::form::ValueField<'a>) -> rocket::form::Result<'a, Self> {
-        let dt = OffsetDateTime::parse(&field.value, &format_description::well_known::Rfc3339)
+        let dt = OffsetDateTime::parse(field.value, &format_description::well_known::Rfc3339)
             .map_err(|_| rocket::form::Error::validation("Invalid date"))?;
         Ok(SerializableDateTime(dt))
     }
@@ -104,21 +225,219 @@ impl From<OffsetDateTime> for SerializableDateTime {
     }
 }
 
-impl Into<OffsetDateTime> for SerializableDateTime {
-    fn into(self) -> OffsetDateTime {
-        self.0
+impl From<SerializableDateTime> for OffsetDateTime {
+    fn from(dt: SerializableDateTime) -> Self {
+        dt.0
+    }
+}
+
+impl From<LineStatusHistoryEntry> for ApiLineStatus {
+    fn from(entry: LineStatusHistoryEntry) -> Self {
+        ApiLineStatus {
+            entries: entry
+                .statuses
+                .into_iter()
+                .map(ApiLineStatusEntry::from)
+                .collect::<Vec<_>>(),
+            from: entry.start_time.into(),
+            to: entry.end_time.map(SerializableDateTime::from),
+        }
+    }
+}
+
+impl From<StationStatusHistoryEntry> for ApiStationStatus {
+    fn from(entry: StationStatusHistoryEntry) -> Self {
+        ApiStationStatus {
+            entries: entry
+                .statuses
+                .into_iter()
+                .map(ApiStationStatusEntry::from)
+                .collect::<Vec<_>>(),
+            from: entry.start_time.into(),
+            to: entry.end_time.map(SerializableDateTime::from),
+        }
+    }
+}
+
+impl From<CollectionGap> for ApiCollectionGap {
+    fn from(gap: CollectionGap) -> Self {
+        ApiCollectionGap {
+            from: gap.start_time.into(),
+            to: gap.end_time.map(SerializableDateTime::from),
+        }
+    }
+}
+
+impl From<LineStatus> for ApiLineStatusEntry {
+    fn from(status: LineStatus) -> Self {
+        ApiLineStatusEntry {
+            status: status.status,
+            reason: status.reason,
+            validity_periods: status
+                .validity_periods
+                .into_iter()
+                .map(|period| ApiValidityPeriod {
+                    from: period.from.into(),
+                    to: period.to.into(),
+                    is_now: period.is_now,
+                })
+                .collect(),
+            disruption: status.disruption,
+        }
+    }
+}
+
+impl ApiHealth {
+    fn new(health: PollerHealth, store: StoreHealth) -> Self {
+        let (breaker, paused_until) = match health.state {
+            BreakerState::Closed => (ApiBreakerState::Closed, None),
+            BreakerState::Open { until } => (ApiBreakerState::Open, Some(until.into())),
+            BreakerState::HalfOpen => (ApiBreakerState::HalfOpen, None),
+        };
+        ApiHealth {
+            breaker,
+            paused_until,
+            consecutive_failures: health.consecutive_failures,
+            last_success: health.last_success.map(SerializableDateTime::from),
+            last_failure: health.last_failure.map(SerializableDateTime::from),
+            last_error: health.last_error,
+            store: store.into(),
+        }
+    }
+}
+
+impl From<StoreHealth> for ApiStoreHealth {
+    fn from(health: StoreHealth) -> Self {
+        ApiStoreHealth {
+            last_success: health.last_success.map(SerializableDateTime::from),
+            last_failure: health.last_failure.map(SerializableDateTime::from),
+            last_error: health.last_error,
+        }
+    }
+}
+
+impl From<StationStatus> for ApiStationStatusEntry {
+    fn from(status: StationStatus) -> Self {
+        ApiStationStatusEntry {
+            status: status.status,
+            description: status.description,
+        }
+    }
+}
+
+impl From<LineTransition> for ApiLineEvent {
+    fn from(transition: LineTransition) -> Self {
+        ApiLineEvent {
+            line: transition.line,
+            time: transition.time.into(),
+            mode: transition.mode,
+            previous: transition
+                .previous
+                .map(|statuses| statuses.into_iter().map(Into::into).collect()),
+            current: transition
+                .current
+                .map(|statuses| statuses.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl From<StationTransition> for ApiStationEvent {
+    fn from(transition: StationTransition) -> Self {
+        ApiStationEvent {
+            station: transition.station,
+            time: transition.time.into(),
+            previous: transition.previous.into_iter().map(Into::into).collect(),
+            current: transition.current.into_iter().map(Into::into).collect(),
+        }
     }
 }
 
-#[get("/v1/history?<from>&<to>")]
+/// Builds the response for the live status, only including the lines and stations accepted by
+/// the filters.
+pub(super) fn current_status_response(
+    current: &CurrentStatus,
+    stale_after_secs: u64,
+    include_line: impl Fn(&str, &CurrentLineStatus) -> bool,
+    include_station: impl Fn(&str) -> bool,
+) -> ApiCurrentStatus {
+    let stale_after = (stale_after_secs as i64).seconds();
+    let lines = current
+        .lines
+        .iter()
+        .filter(|(line, status)| include_line(line, status))
+        .map(|(line, status)| {
+            let entries = status
+                .statuses
+                .iter()
+                .cloned()
+                .map(ApiLineStatusEntry::from)
+                .collect::<Vec<_>>();
+            (
+                line.clone(),
+                ApiCurrentLineStatus {
+                    entries,
+                    metadata: ApiLineMetadata {
+                        mode: Some(status.mode.clone()),
+                    },
+                },
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let stations = current
+        .stations
+        .iter()
+        .filter(|(station, _)| include_station(station))
+        .map(|(station, statuses)| {
+            let entries = statuses
+                .iter()
+                .cloned()
+                .map(ApiStationStatusEntry::from)
+                .collect::<Vec<_>>();
+            (station.clone(), ApiCurrentStationStatus { entries })
+        })
+        .collect::<HashMap<_, _>>();
+    ApiCurrentStatus {
+        last_updated: current.updated.into(),
+        stale: OffsetDateTime::now_utc() - current.updated > stale_after,
+        lines,
+        stations,
+    }
+}
+
+/// The instant to take a status snapshot at, either an RFC3339 date or `now`.
+#[derive(Debug, Clone)]
+enum SnapshotTime {
+    Now,
+    At(SerializableDateTime),
+}
+
+impl<'a> FromFormField<'a> for SnapshotTime {
+    fn from_value(field: rocket::form::ValueField<'a>) -> rocket::form::Result<'a, Self> {
+        if field.value == "now" {
+            Ok(SnapshotTime::Now)
+        } else {
+            Ok(SnapshotTime::At(SerializableDateTime::from_value(field)?))
+        }
+    }
+}
+
+#[get("/v1/history?<from>&<to>&<line>&<mode>&<min_severity>")]
 async fn line_history(
     mut store: StoreConnection,
     from: SerializableDateTime,
     to: SerializableDateTime,
+    line: Vec<String>,
+    mode: Vec<String>,
+    min_severity: Option<LineState>,
 ) -> Result<Json<HashMap<String, ApiLineHistory>>, rocket::http::Status> {
     check_time_range(&from, &to)?;
+    let filter = LineHistoryFilter {
+        lines: line,
+        modes: mode,
+        min_severity,
+    };
     let status_history = store
-        .get_line_status_history(from.into(), to.into())
+        .get_line_status_history(from.into(), to.into(), &filter)
         .await
         .map_err(|e| {
             error!("Error getting status history: {:?}", e);
@@ -127,44 +446,16 @@ async fn line_history(
     let response = status_history
         .into_iter()
         .map(|(line, entries)| {
-            let (history, metadata) = entries
+            let mode = entries.last().map(|entry| entry.mode.clone());
+            let history = entries
                 .into_iter()
-                .filter_map(|entry| {
-                    let (metadata, parsed_entries) =
-                        tfl::try_parse_line_status(&line, &entry.data)?;
-                    Some((
-                        ApiLineStatus {
-                            entries: parsed_entries
-                                .into_iter()
-                                .map(|e| ApiLineStatusEntry {
-                                    status: e.status,
-                                    reason: e.reason,
-                                })
-                                .collect::<Vec<_>>(),
-                            from: entry.start_time.into(),
-                            to: entry.end_time.map(SerializableDateTime::from),
-                        },
-                        metadata,
-                    ))
-                })
-                .fold(
-                    (Vec::<ApiLineStatus>::new(), None),
-                    |(mut acc, _), (status, metadata)| {
-                        if let Some(last_status) = acc.last_mut() {
-                            if last_status.entries == status.entries {
-                                last_status.to = status.to;
-                                return (acc, Some(metadata));
-                            }
-                        }
-                        acc.push(status);
-                        (acc, Some(metadata))
-                    },
-                );
+                .map(ApiLineStatus::from)
+                .collect::<Vec<_>>();
             (
                 line,
                 ApiLineHistory {
                     history,
-                    metadata: metadata.into(),
+                    metadata: ApiLineMetadata { mode },
                 },
             )
         })
@@ -172,15 +463,21 @@ async fn line_history(
     Ok(Json(response))
 }
 
-#[get("/v1/station-history?<from>&<to>")]
+#[get("/v1/station-history?<from>&<to>&<station>&<min_severity>")]
 async fn station_history(
     mut store: StoreConnection,
     from: SerializableDateTime,
     to: SerializableDateTime,
+    station: Vec<String>,
+    min_severity: Option<StationState>,
 ) -> Result<Json<HashMap<String, ApiStationHistory>>, rocket::http::Status> {
     check_time_range(&from, &to)?;
+    let filter = StationHistoryFilter {
+        stations: station,
+        min_severity,
+    };
     let status_history = store
-        .get_station_status_history(from.into(), to.into())
+        .get_station_status_history(from.into(), to.into(), &filter)
         .await
         .map_err(|e| {
             error!("Error getting station status history: {:?}", e);
@@ -191,36 +488,165 @@ async fn station_history(
         .map(|(station, entries)| {
             let history = entries
                 .into_iter()
-                .filter_map(|entry| {
-                    let parsed_entries = tfl::try_parse_station_status(&station, &entry.data)?;
-                    Some(ApiStationStatus {
-                        entries: parsed_entries
-                            .into_iter()
-                            .map(|e| ApiStationStatusEntry {
-                                status: e.status,
-                                description: e.description,
-                            })
-                            .collect::<Vec<_>>(),
-                        from: entry.start_time.into(),
-                        to: entry.end_time.map(SerializableDateTime::from),
-                    })
-                })
-                .fold(Vec::<ApiStationStatus>::new(), |mut acc, status| {
-                    if let Some(last_status) = acc.last_mut() {
-                        if last_status.entries == status.entries {
-                            last_status.to = status.to;
-                            return acc;
-                        }
-                    }
-                    acc.push(status);
-                    acc
-                });
+                .map(ApiStationStatus::from)
+                .collect::<Vec<_>>();
             (station, ApiStationHistory { history })
         })
         .collect::<HashMap<_, _>>();
     Ok(Json(response))
 }
 
+/// Gets the periods when the line and station status weren't being collected, which should be
+/// shown as unknown rather than as whatever status came before.
+#[get("/v1/history/gaps?<from>&<to>")]
+async fn collection_gaps(
+    mut store: StoreConnection,
+    tfl: &State<Arc<Tfl>>,
+    from: SerializableDateTime,
+    to: SerializableDateTime,
+) -> Result<Json<ApiCollectionGaps>, rocket::http::Status> {
+    check_time_range(&from, &to)?;
+    let gap_after = tfl.gap_after();
+    let log_error = |e| {
+        error!("Error getting collection gaps: {:?}", e);
+        rocket::http::Status::InternalServerError
+    };
+    let lines = get_collection_gaps(&mut store, PollTarget::Lines, &from, &to, gap_after)
+        .await
+        .map_err(log_error)?;
+    let stations = get_collection_gaps(&mut store, PollTarget::Stations, &from, &to, gap_after)
+        .await
+        .map_err(log_error)?;
+    Ok(Json(ApiCollectionGaps {
+        lines: lines.into_iter().map(ApiCollectionGap::from).collect(),
+        stations: stations.into_iter().map(ApiCollectionGap::from).collect(),
+    }))
+}
+
+/// Gets the recorded gaps in collecting a target, along with the gap since it was last polled if
+/// that's gone on for long enough.
+async fn get_collection_gaps(
+    store: &mut StoreConnection,
+    target: PollTarget,
+    from: &SerializableDateTime,
+    to: &SerializableDateTime,
+    gap_after: time::Duration,
+) -> Result<Vec<CollectionGap>, GetStatusError> {
+    let mut gaps = store
+        .get_collection_gaps(target.name(), from.0, to.0)
+        .await?;
+    let last_poll = store.get_last_poll(target.name()).await?;
+    if let Some(last_poll) = last_poll {
+        if OffsetDateTime::now_utc() - last_poll > gap_after && last_poll <= to.0 {
+            gaps.push(CollectionGap {
+                start_time: last_poll,
+                end_time: None,
+            });
+        }
+    }
+    Ok(gaps)
+}
+
+#[get("/v1/status")]
+async fn status(
+    tfl: &State<Arc<Tfl>>,
+    config: &State<Config>,
+) -> Result<Json<ApiCurrentStatus>, rocket::http::Status> {
+    let current = tfl.current_status().ok_or_else(|| {
+        warn!("Live status requested before the first successful poll");
+        rocket::http::Status::ServiceUnavailable
+    })?;
+    Ok(Json(current_status_response(
+        &current,
+        config.status_stale_after_secs,
+        |_, _| true,
+        |_| true,
+    )))
+}
+
+#[get("/v1/status-at?<time>")]
+async fn status_at(
+    mut store: StoreConnection,
+    time: SnapshotTime,
+) -> Result<Json<ApiStatusAt>, rocket::http::Status> {
+    // Serve "now" from the open rows, so it isn't affected by clock skew with the poller
+    let (at, response_time) = match time {
+        SnapshotTime::Now => (None, OffsetDateTime::now_utc()),
+        SnapshotTime::At(time) => (Some(time.0), time.0),
+    };
+    let line_status = store.get_line_status_at(at).await.map_err(|e| {
+        error!("Error getting line status snapshot: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    let station_status = store.get_station_status_at(at).await.map_err(|e| {
+        error!("Error getting station status snapshot: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    let lines = line_status
+        .into_iter()
+        .map(|(line, entry)| {
+            let metadata = ApiLineMetadata {
+                mode: Some(entry.mode.clone()),
+            };
+            (
+                line,
+                ApiLineSnapshot {
+                    status: entry.into(),
+                    metadata,
+                },
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let stations = station_status
+        .into_iter()
+        .map(|(station, entry)| (station, entry.into()))
+        .collect::<HashMap<_, _>>();
+    Ok(Json(ApiStatusAt {
+        time: response_time.into(),
+        lines,
+        stations,
+    }))
+}
+
+/// Streams line and station status changes as they're recorded. If any `line` or `station`
+/// filters are given, only changes to those lines and stations are sent.
+#[get("/v1/events?<line>&<station>")]
+fn events(
+    events: &State<EventBus>,
+    line: Vec<String>,
+    station: Vec<String>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut receiver = events.subscribe();
+    let unfiltered = line.is_empty() && station.is_empty();
+    EventStream! {
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Event stream fell behind, skipped {} events", skipped);
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+            match event {
+                StatusEvent::Line(transition) if unfiltered || line.contains(&transition.line) => {
+                    yield Event::json(&ApiLineEvent::from(transition)).event("line");
+                }
+                StatusEvent::Station(transition)
+                    if unfiltered || station.contains(&transition.station) =>
+                {
+                    yield Event::json(&ApiStationEvent::from(transition)).event("station");
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
 #[get("/v1/station-details")]
 async fn station_details(
     loaded_details: &State<Arc<LoadedStationDetails>>,
@@ -247,6 +673,13 @@ async fn station_details(
     Ok(Json(response))
 }
 
+/// Reports whether polling TfL is working, or paused by the circuit breaker after repeated
+/// failures, and whether the polled status is being recorded in the store.
+#[get("/v1/health")]
+fn health(tfl: &State<Arc<Tfl>>) -> Json<ApiHealth> {
+    Json(ApiHealth::new(tfl.health(), tfl.store_health()))
+}
+
 fn check_time_range(
     from: &SerializableDateTime,
     to: &SerializableDateTime,
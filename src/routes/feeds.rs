@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use rocket::http::ContentType;
+use rocket::Route;
+use time::ext::NumericalDuration;
+use time::{format_description, OffsetDateTime};
+
+use crate::localtime;
+use crate::store::{LineHistoryFilter, StoreConnection};
+use crate::types::{LineState, LineStatusHistoryEntry};
+
+/// How far back the feeds go.
+const FEED_DAYS: i64 = 7;
+/// The most entries included in a feed, newest first.
+const MAX_ENTRIES: usize = 100;
+/// The least severe state which gets a feed entry.
+const MIN_FEED_SEVERITY: LineState = LineState::MinorDelays;
+/// How far back the calendars go. Closures are announced ahead of time, so they also include
+/// any upcoming closures that TfL has told us about.
+const CALENDAR_DAYS: i64 = 30;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![all_lines_feed, line_feed]
+}
+
+/// An Atom feed of every line's disruptions.
+#[get("/feeds/lines.atom")]
+async fn all_lines_feed(
+    store: StoreConnection,
+) -> Result<(ContentType, String), rocket::http::Status> {
+    feed(store, None, "/feeds/lines.atom").await
+}
+
+/// An Atom feed of one line's disruptions, like `/feeds/lines/victoria.atom`, or an iCalendar
+/// feed of its planned closures, like `/feeds/lines/victoria.ics`.
+#[get("/feeds/lines/<file>")]
+async fn line_feed(
+    store: StoreConnection,
+    file: &str,
+) -> Option<Result<(ContentType, String), rocket::http::Status>> {
+    if let Some(line) = file.strip_suffix(".atom") {
+        Some(feed(store, Some(line), &format!("/feeds/lines/{}", file)).await)
+    } else if let Some(line) = file.strip_suffix(".ics") {
+        Some(calendar(store, line).await)
+    } else {
+        None
+    }
+}
+
+/// Builds a feed with an entry for each period during which a line wasn't running normally. Lines
+/// being closed for the night are running normally, so don't get entries.
+async fn feed(
+    mut store: StoreConnection,
+    line: Option<&str>,
+    path: &str,
+) -> Result<(ContentType, String), rocket::http::Status> {
+    let now = OffsetDateTime::now_utc();
+    let filter = LineHistoryFilter {
+        lines: line.map(|line| vec![line.to_string()]).unwrap_or_default(),
+        min_severity: Some(MIN_FEED_SEVERITY),
+        ..Default::default()
+    };
+    let history = store
+        .get_line_status_history(now - FEED_DAYS.days(), now, &filter)
+        .await
+        .map_err(|e| {
+            error!("Error getting status history for feed: {:?}", e);
+            rocket::http::Status::InternalServerError
+        })?;
+    let mut entries: Vec<_> = history
+        .iter()
+        .flat_map(|(line, entries)| entries.iter().map(move |entry| (line.as_str(), entry)))
+        .filter(|(_, entry)| {
+            entry
+                .statuses
+                .iter()
+                .any(|status| status.status.is_disruption_at_least(MIN_FEED_SEVERITY))
+        })
+        .collect();
+    entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.start_time));
+    entries.truncate(MAX_ENTRIES);
+
+    let title = match line {
+        Some(line) => format!("Severe Delays: {} disruptions", line),
+        None => "Severe Delays: line disruptions".to_string(),
+    };
+    let updated = entries
+        .iter()
+        .map(|(_, entry)| entry_updated(entry))
+        .max()
+        .unwrap_or(now);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    writeln!(xml, "  <id>urn:severe-delays:feed:{}</id>", escape(path)).unwrap();
+    writeln!(xml, "  <title>{}</title>", escape(&title)).unwrap();
+    writeln!(xml, "  <updated>{}</updated>", format_time(updated)).unwrap();
+    writeln!(xml, "  <link rel=\"self\" href=\"{}\"/>", escape(path)).unwrap();
+    xml.push_str("  <link rel=\"alternate\" href=\"/\"/>\n");
+    xml.push_str("  <author><name>Severe Delays</name></author>\n");
+    for (line, entry) in entries {
+        write_entry(&mut xml, line, entry);
+    }
+    xml.push_str("</feed>\n");
+    Ok((ContentType::new("application", "atom+xml"), xml))
+}
+
+fn write_entry(xml: &mut String, line: &str, entry: &LineStatusHistoryEntry) {
+    let state = entry
+        .statuses
+        .iter()
+        .map(|status| status.status)
+        .min()
+        .map_or("Unknown", LineState::description);
+    let title = match entry.end_time {
+        Some(_) => format!("{}: {}", line, state),
+        None => format!("{}: {} (ongoing)", line, state),
+    };
+    let mut content = match entry.end_time {
+        Some(end_time) => format!(
+            "From {} until {}.\n",
+            localtime::describe(entry.start_time),
+            localtime::describe(end_time)
+        ),
+        None => format!("Since {}.\n", localtime::describe(entry.start_time)),
+    };
+    for status in &entry.statuses {
+        match &status.reason {
+            Some(reason) => writeln!(content, "\n{}: {}", status.status.description(), reason),
+            None => writeln!(content, "\n{}", status.status.description()),
+        }
+        .unwrap();
+    }
+
+    xml.push_str("  <entry>\n");
+    writeln!(
+        xml,
+        "    <id>urn:severe-delays:line:{}:{}</id>",
+        escape(line),
+        entry.start_time.unix_timestamp()
+    )
+    .unwrap();
+    writeln!(xml, "    <title>{}</title>", escape(&title)).unwrap();
+    writeln!(
+        xml,
+        "    <published>{}</published>",
+        format_time(entry.start_time)
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "    <updated>{}</updated>",
+        format_time(entry_updated(entry))
+    )
+    .unwrap();
+    writeln!(xml, "    <category term=\"{}\"/>", escape(&entry.mode)).unwrap();
+    writeln!(
+        xml,
+        "    <content type=\"text\">{}</content>",
+        escape(content.trim_end())
+    )
+    .unwrap();
+    xml.push_str("  </entry>\n");
+}
+
+/// Periods are updated when they end, since that's when their entry last changed.
+fn entry_updated(entry: &LineStatusHistoryEntry) -> OffsetDateTime {
+    entry.end_time.unwrap_or(entry.start_time)
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    time.format(&format_description::well_known::Rfc3339)
+        .unwrap()
+}
+
+/// Builds a calendar with an event for each period during which part or all of a line is
+/// closed for planned works.
+async fn calendar(
+    mut store: StoreConnection,
+    line: &str,
+) -> Result<(ContentType, String), rocket::http::Status> {
+    let now = OffsetDateTime::now_utc();
+    let since = now - CALENDAR_DAYS.days();
+    let filter = LineHistoryFilter {
+        lines: vec![line.to_string()],
+        ..Default::default()
+    };
+    let mut history = store
+        .get_line_status_history(since, now, &filter)
+        .await
+        .map_err(|e| {
+            error!("Error getting status history for calendar: {:?}", e);
+            rocket::http::Status::InternalServerError
+        })?;
+    let mut entries = history.remove(line).unwrap_or_default();
+    entries.sort_by_key(|entry| entry.start_time);
+    // The same closures are repeated in every status until they're over, so later statuses
+    // replace the details from earlier ones.
+    let mut events = BTreeMap::new();
+    for entry in &entries {
+        let closures = entry.statuses.iter().filter(|status| {
+            matches!(
+                status.status,
+                LineState::PlannedClosure | LineState::PartClosure
+            )
+        });
+        for closure in closures {
+            for period in &closure.validity_periods {
+                if period.to >= since {
+                    events.insert(
+                        (period.from, period.to, closure.status),
+                        closure.reason.clone(),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut ics = String::new();
+    push_ics_line(&mut ics, "BEGIN:VCALENDAR");
+    push_ics_line(&mut ics, "VERSION:2.0");
+    push_ics_line(&mut ics, "PRODID:-//Severe Delays//Planned closures//EN");
+    push_ics_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_ics_line(
+        &mut ics,
+        &format!(
+            "X-WR-CALNAME:{}",
+            escape_text(&format!("Severe Delays: {} closures", line))
+        ),
+    );
+    for ((from, to, state), reason) in events {
+        push_ics_line(&mut ics, "BEGIN:VEVENT");
+        push_ics_line(
+            &mut ics,
+            &format!(
+                "UID:{}-{:?}-{}-{}@severe-delays",
+                line,
+                state,
+                from.unix_timestamp(),
+                to.unix_timestamp()
+            ),
+        );
+        push_ics_line(&mut ics, &format!("DTSTAMP:{}", format_ics_time(now)));
+        push_ics_line(&mut ics, &format!("DTSTART:{}", format_ics_time(from)));
+        push_ics_line(&mut ics, &format!("DTEND:{}", format_ics_time(to)));
+        push_ics_line(
+            &mut ics,
+            &format!(
+                "SUMMARY:{}",
+                escape_text(&format!("{}: {}", line, state.description()))
+            ),
+        );
+        if let Some(reason) = reason {
+            push_ics_line(&mut ics, &format!("DESCRIPTION:{}", escape_text(&reason)));
+        }
+        push_ics_line(&mut ics, "TRANSP:TRANSPARENT");
+        push_ics_line(&mut ics, "END:VEVENT");
+    }
+    push_ics_line(&mut ics, "END:VCALENDAR");
+    Ok((
+        ContentType::new("text", "calendar").with_params(("charset", "utf-8")),
+        ics,
+    ))
+}
+
+/// Appends a content line, folding it so that no line is longer than 75 bytes.
+fn push_ics_line(ics: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            length = 1;
+        }
+        ics.push(c);
+        length += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn format_ics_time(time: OffsetDateTime) -> String {
+    let time = time.to_offset(time::UtcOffset::UTC);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
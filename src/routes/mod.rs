@@ -1,3 +1,9 @@
+pub mod admin;
 pub mod api;
 pub mod fe;
+pub mod feeds;
+pub mod push;
+pub mod subscriptions;
 pub mod utils;
+pub mod webhooks;
+pub mod ws;
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use rocket::response::status::NoContent;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::push::{self, Push};
+use crate::store::StoreConnection;
+use crate::types::{LineState, PushSubscription};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![public_key, add_subscription, delete_subscription]
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiPublicKey {
+    public_key: String,
+}
+
+/// A browser's registration, where `subscription` is the result of `PushSubscription.toJSON()`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiNewPushSubscription {
+    subscription: ApiBrowserSubscription,
+    #[serde(default)]
+    lines: Vec<String>,
+    #[serde(default)]
+    stations: Vec<String>,
+    min_line_severity: Option<LineState>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiBrowserSubscription {
+    endpoint: String,
+    keys: ApiBrowserSubscriptionKeys,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiBrowserSubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+/// Gets the key that browsers need to subscribe to push notifications from this server.
+#[get("/v1/push/public-key")]
+fn public_key(push: &State<Option<Arc<Push>>>) -> Option<Json<ApiPublicKey>> {
+    Some(Json(ApiPublicKey {
+        public_key: push.as_ref()?.public_key(),
+    }))
+}
+
+/// Registers a browser for notifications, or updates the lines and stations it's interested in.
+#[post("/v1/push/subscriptions", data = "<subscription>")]
+async fn add_subscription(
+    push: &State<Option<Arc<Push>>>,
+    mut store: StoreConnection,
+    subscription: Json<ApiNewPushSubscription>,
+) -> Result<NoContent, rocket::http::Status> {
+    if push.is_none() {
+        return Err(rocket::http::Status::NotFound);
+    }
+    let subscription = subscription.into_inner();
+    let browser = subscription.subscription;
+    let valid_endpoint =
+        reqwest::Url::parse(&browser.endpoint).is_ok_and(|url| url.scheme() == "https");
+    let valid_keys = push::parse_keys(&browser.keys.p256dh, &browser.keys.auth).is_some();
+    if !valid_endpoint || !valid_keys {
+        warn!(
+            "Rejecting invalid push subscription for {}",
+            browser.endpoint
+        );
+        return Err(rocket::http::Status::BadRequest);
+    }
+    let subscription = PushSubscription {
+        endpoint: browser.endpoint,
+        p256dh: browser.keys.p256dh,
+        auth: browser.keys.auth,
+        lines: subscription.lines,
+        stations: subscription.stations,
+        min_line_severity: subscription.min_line_severity,
+    };
+    store
+        .set_push_subscription(&subscription)
+        .await
+        .map_err(|e| {
+            error!("Error adding push subscription: {:?}", e);
+            rocket::http::Status::InternalServerError
+        })?;
+    Ok(NoContent)
+}
+
+/// Unregisters a browser. Knowing its endpoint is enough, since it's unguessable.
+#[delete("/v1/push/subscriptions?<endpoint>")]
+async fn delete_subscription(
+    mut store: StoreConnection,
+    endpoint: &str,
+) -> Result<NoContent, rocket::http::Status> {
+    let deleted = store
+        .delete_push_subscription(endpoint)
+        .await
+        .map_err(|e| {
+            error!("Error deleting push subscription: {:?}", e);
+            rocket::http::Status::InternalServerError
+        })?;
+    if deleted {
+        Ok(NoContent)
+    } else {
+        Err(rocket::http::Status::NotFound)
+    }
+}
@@ -0,0 +1,217 @@
+use rocket::response::status::{Created, NoContent};
+use rocket::serde::json::Json;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use super::admin::Admin;
+use crate::email;
+use crate::localtime;
+use crate::store::{StoreConnection, StoredSubscription};
+use crate::types::{LineState, NotificationTarget, Subscription};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        list_subscriptions,
+        get_subscription,
+        add_subscription,
+        update_subscription,
+        delete_subscription
+    ]
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiSubscription {
+    id: i64,
+    name: String,
+    lines: Vec<String>,
+    min_severity: LineState,
+    active_from: Option<String>,
+    active_until: Option<String>,
+    days: Vec<String>,
+    target: ApiNotificationTarget,
+}
+
+/// Where alerts are sent. Secrets aren't included, since they can't be read back.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ApiNotificationTarget {
+    Webhook { url: String },
+    Email { address: String },
+}
+
+/// A subscription as sent by the client, e.g. `{"name": "Commute", "lines": ["victoria"],
+/// "minSeverity": "SevereDelays", "activeFrom": "07:00", "activeUntil": "09:30",
+/// "days": ["monday", "tuesday"], "target": {"type": "webhook", "url": "...", "secret": "..."}}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiNewSubscription {
+    name: String,
+    #[serde(default)]
+    lines: Vec<String>,
+    min_severity: LineState,
+    active_from: Option<String>,
+    active_until: Option<String>,
+    #[serde(default)]
+    days: Vec<String>,
+    target: NotificationTarget,
+}
+
+impl From<StoredSubscription> for ApiSubscription {
+    fn from(stored: StoredSubscription) -> Self {
+        let subscription = stored.subscription;
+        ApiSubscription {
+            id: stored.id,
+            name: subscription.name,
+            lines: subscription.lines,
+            min_severity: subscription.min_severity,
+            active_from: subscription.active_from.map(localtime::format_time_of_day),
+            active_until: subscription.active_until.map(localtime::format_time_of_day),
+            days: subscription
+                .days
+                .iter()
+                .map(|day| day.to_string().to_lowercase())
+                .collect(),
+            target: match subscription.target {
+                NotificationTarget::Webhook { url, .. } => ApiNotificationTarget::Webhook { url },
+                NotificationTarget::Email { address } => ApiNotificationTarget::Email { address },
+            },
+        }
+    }
+}
+
+impl TryFrom<ApiNewSubscription> for Subscription {
+    type Error = String;
+
+    fn try_from(subscription: ApiNewSubscription) -> Result<Self, Self::Error> {
+        let parse_time = |value: Option<String>| {
+            value
+                .map(|value| {
+                    localtime::parse_time_of_day(&value)
+                        .ok_or_else(|| format!("Invalid time of day: {}", value))
+                })
+                .transpose()
+        };
+        let days = subscription
+            .days
+            .iter()
+            .map(|day| localtime::parse_weekday(day).ok_or_else(|| format!("Invalid day: {}", day)))
+            .collect::<Result<Vec<_>, _>>()?;
+        match &subscription.target {
+            NotificationTarget::Webhook { url, secret } => {
+                let valid_url = reqwest::Url::parse(url)
+                    .is_ok_and(|url| url.scheme() == "http" || url.scheme() == "https");
+                if !valid_url || secret.is_empty() {
+                    return Err(format!("Invalid webhook target: {}", url));
+                }
+            }
+            NotificationTarget::Email { address } => {
+                if !email::is_valid_address(address) {
+                    return Err(format!("Invalid email address: {}", address));
+                }
+            }
+        }
+        Ok(Subscription {
+            name: subscription.name,
+            lines: subscription.lines,
+            min_severity: subscription.min_severity,
+            active_from: parse_time(subscription.active_from)?,
+            active_until: parse_time(subscription.active_until)?,
+            days,
+            target: subscription.target,
+        })
+    }
+}
+
+#[get("/v1/subscriptions")]
+async fn list_subscriptions(
+    _admin: Admin,
+    mut store: StoreConnection,
+) -> Result<Json<Vec<ApiSubscription>>, rocket::http::Status> {
+    let subscriptions = store.get_subscriptions().await.map_err(|e| {
+        error!("Error getting subscriptions: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    Ok(Json(
+        subscriptions
+            .into_iter()
+            .map(ApiSubscription::from)
+            .collect(),
+    ))
+}
+
+#[get("/v1/subscriptions/<id>")]
+async fn get_subscription(
+    _admin: Admin,
+    mut store: StoreConnection,
+    id: i64,
+) -> Result<Json<ApiSubscription>, rocket::http::Status> {
+    let subscription = store.get_subscription(id).await.map_err(|e| {
+        error!("Error getting subscription: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    subscription
+        .map(|subscription| Json(subscription.into()))
+        .ok_or(rocket::http::Status::NotFound)
+}
+
+#[post("/v1/subscriptions", data = "<subscription>")]
+async fn add_subscription(
+    _admin: Admin,
+    mut store: StoreConnection,
+    subscription: Json<ApiNewSubscription>,
+) -> Result<Created<Json<ApiSubscription>>, rocket::http::Status> {
+    let subscription = parse_subscription(subscription.into_inner())?;
+    let stored = store.add_subscription(&subscription).await.map_err(|e| {
+        error!("Error adding subscription: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    Ok(Created::new(format!("/api/v1/subscriptions/{}", stored.id)).body(Json(stored.into())))
+}
+
+#[put("/v1/subscriptions/<id>", data = "<subscription>")]
+async fn update_subscription(
+    _admin: Admin,
+    mut store: StoreConnection,
+    id: i64,
+    subscription: Json<ApiNewSubscription>,
+) -> Result<Json<ApiSubscription>, rocket::http::Status> {
+    let subscription = parse_subscription(subscription.into_inner())?;
+    let updated = store
+        .update_subscription(id, &subscription)
+        .await
+        .map_err(|e| {
+            error!("Error updating subscription: {:?}", e);
+            rocket::http::Status::InternalServerError
+        })?;
+    if !updated {
+        return Err(rocket::http::Status::NotFound);
+    }
+    Ok(Json(StoredSubscription { id, subscription }.into()))
+}
+
+#[delete("/v1/subscriptions/<id>")]
+async fn delete_subscription(
+    _admin: Admin,
+    mut store: StoreConnection,
+    id: i64,
+) -> Result<NoContent, rocket::http::Status> {
+    let deleted = store.delete_subscription(id).await.map_err(|e| {
+        error!("Error deleting subscription: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    if deleted {
+        Ok(NoContent)
+    } else {
+        Err(rocket::http::Status::NotFound)
+    }
+}
+
+fn parse_subscription(
+    subscription: ApiNewSubscription,
+) -> Result<Subscription, rocket::http::Status> {
+    Subscription::try_from(subscription).map_err(|e| {
+        warn!("Rejecting invalid subscription: {}", e);
+        rocket::http::Status::BadRequest
+    })
+}
@@ -0,0 +1,165 @@
+use rocket::response::status::{Created, NoContent};
+use rocket::serde::json::Json;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::admin::Admin;
+use super::api::SerializableDateTime;
+use crate::config::WebhookTarget;
+use crate::store::{StoreConnection, Webhook, WebhookDeadLetter};
+use crate::types::{LineState, StationState};
+
+const DEFAULT_DEAD_LETTER_LIMIT: i64 = 100;
+const MAX_DEAD_LETTER_LIMIT: i64 = 1000;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![list_webhooks, add_webhook, delete_webhook, dead_letters]
+}
+
+/// A registered webhook. The secret isn't included, since it can't be changed.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiWebhook {
+    id: i64,
+    url: String,
+    lines: Vec<String>,
+    modes: Vec<String>,
+    stations: Vec<String>,
+    min_line_severity: Option<LineState>,
+    min_station_severity: Option<StationState>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiNewWebhook {
+    url: String,
+    secret: String,
+    #[serde(default)]
+    lines: Vec<String>,
+    #[serde(default)]
+    modes: Vec<String>,
+    #[serde(default)]
+    stations: Vec<String>,
+    min_line_severity: Option<LineState>,
+    min_station_severity: Option<StationState>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiWebhookDeadLetter {
+    webhook_id: Option<i64>,
+    url: String,
+    payload: Value,
+    error: String,
+    attempts: i64,
+    failed_at: SerializableDateTime,
+}
+
+impl From<Webhook> for ApiWebhook {
+    fn from(webhook: Webhook) -> Self {
+        ApiWebhook {
+            id: webhook.id,
+            url: webhook.target.url,
+            lines: webhook.target.lines,
+            modes: webhook.target.modes,
+            stations: webhook.target.stations,
+            min_line_severity: webhook.target.min_line_severity,
+            min_station_severity: webhook.target.min_station_severity,
+        }
+    }
+}
+
+impl From<WebhookDeadLetter> for ApiWebhookDeadLetter {
+    fn from(dead_letter: WebhookDeadLetter) -> Self {
+        ApiWebhookDeadLetter {
+            webhook_id: dead_letter.webhook_id,
+            url: dead_letter.url,
+            payload: serde_json::from_str(&dead_letter.payload)
+                .unwrap_or(Value::String(dead_letter.payload)),
+            error: dead_letter.error,
+            attempts: dead_letter.attempts,
+            failed_at: dead_letter.failed_at.into(),
+        }
+    }
+}
+
+#[get("/v1/webhooks")]
+async fn list_webhooks(
+    _admin: Admin,
+    mut store: StoreConnection,
+) -> Result<Json<Vec<ApiWebhook>>, rocket::http::Status> {
+    let webhooks = store.get_webhooks().await.map_err(|e| {
+        error!("Error getting webhooks: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    Ok(Json(webhooks.into_iter().map(ApiWebhook::from).collect()))
+}
+
+#[post("/v1/webhooks", data = "<webhook>")]
+async fn add_webhook(
+    _admin: Admin,
+    mut store: StoreConnection,
+    webhook: Json<ApiNewWebhook>,
+) -> Result<Created<Json<ApiWebhook>>, rocket::http::Status> {
+    let webhook = webhook.into_inner();
+    let valid_url = reqwest::Url::parse(&webhook.url)
+        .is_ok_and(|url| url.scheme() == "http" || url.scheme() == "https");
+    if !valid_url || webhook.secret.is_empty() {
+        warn!("Rejecting invalid webhook for {}", webhook.url);
+        return Err(rocket::http::Status::BadRequest);
+    }
+    let target = WebhookTarget {
+        url: webhook.url,
+        secret: webhook.secret,
+        lines: webhook.lines,
+        modes: webhook.modes,
+        stations: webhook.stations,
+        min_line_severity: webhook.min_line_severity,
+        min_station_severity: webhook.min_station_severity,
+    };
+    let webhook = store.add_webhook(&target).await.map_err(|e| {
+        error!("Error adding webhook: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    Ok(Created::new(format!("/api/v1/webhooks/{}", webhook.id)).body(Json(webhook.into())))
+}
+
+#[delete("/v1/webhooks/<id>")]
+async fn delete_webhook(
+    _admin: Admin,
+    mut store: StoreConnection,
+    id: i64,
+) -> Result<NoContent, rocket::http::Status> {
+    let deleted = store.delete_webhook(id).await.map_err(|e| {
+        error!("Error deleting webhook: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    if deleted {
+        Ok(NoContent)
+    } else {
+        Err(rocket::http::Status::NotFound)
+    }
+}
+
+/// The most recent webhook deliveries which failed after all retries.
+#[get("/v1/webhooks/dead-letters?<limit>")]
+async fn dead_letters(
+    _admin: Admin,
+    mut store: StoreConnection,
+    limit: Option<i64>,
+) -> Result<Json<Vec<ApiWebhookDeadLetter>>, rocket::http::Status> {
+    let limit = limit
+        .unwrap_or(DEFAULT_DEAD_LETTER_LIMIT)
+        .clamp(1, MAX_DEAD_LETTER_LIMIT);
+    let dead_letters = store.get_webhook_dead_letters(limit).await.map_err(|e| {
+        error!("Error getting failed webhook deliveries: {:?}", e);
+        rocket::http::Status::InternalServerError
+    })?;
+    Ok(Json(
+        dead_letters
+            .into_iter()
+            .map(ApiWebhookDeadLetter::from)
+            .collect(),
+    ))
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Route, Shutdown, State};
+use rocket_ws::{Channel, Message, WebSocket};
+use serde::{Deserialize, Serialize};
+
+use super::api::{current_status_response, ApiCurrentStatus, ApiLineEvent, ApiStationEvent};
+use crate::config::Config;
+use crate::events::{EventBus, StatusEvent};
+use crate::tfl::Tfl;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![live]
+}
+
+/// A message sent by the client to change what it's subscribed to.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    Subscribe(SubscriptionFilter),
+    Unsubscribe(SubscriptionFilter),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SubscriptionFilter {
+    lines: Vec<String>,
+    /// Station ATCO codes.
+    stations: Vec<String>,
+    /// Matches every line with one of these modes.
+    modes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    /// The current status of everything that was just subscribed to.
+    Snapshot(ApiCurrentStatus),
+    Line(ApiLineEvent),
+    Station(ApiStationEvent),
+    Error {
+        message: String,
+    },
+}
+
+/// Everything that a connection is subscribed to.
+#[derive(Debug, Default)]
+struct Subscription {
+    lines: HashSet<String>,
+    stations: HashSet<String>,
+    modes: HashSet<String>,
+}
+
+impl Subscription {
+    fn add(&mut self, filter: &SubscriptionFilter) {
+        self.lines.extend(filter.lines.iter().cloned());
+        self.stations.extend(filter.stations.iter().cloned());
+        self.modes.extend(filter.modes.iter().cloned());
+    }
+
+    fn remove(&mut self, filter: &SubscriptionFilter) {
+        for line in &filter.lines {
+            self.lines.remove(line);
+        }
+        for station in &filter.stations {
+            self.stations.remove(station);
+        }
+        for mode in &filter.modes {
+            self.modes.remove(mode);
+        }
+    }
+
+    fn includes_line(&self, line: &str, mode: Option<&str>) -> bool {
+        self.lines.contains(line) || mode.is_some_and(|mode| self.modes.contains(mode))
+    }
+
+    fn includes_station(&self, station: &str) -> bool {
+        self.stations.contains(station)
+    }
+}
+
+/// Live status over a WebSocket. Clients send `subscribe` and `unsubscribe` messages listing
+/// lines, stations and modes, and receive a snapshot of everything they subscribed to followed
+/// by each change to it.
+#[get("/v1/live")]
+fn live(
+    ws: WebSocket,
+    tfl: &State<Arc<Tfl>>,
+    events: &State<EventBus>,
+    config: &State<Config>,
+    mut shutdown: Shutdown,
+) -> Channel<'static> {
+    let tfl = tfl.inner().clone();
+    let stale_after_secs = config.status_stale_after_secs;
+    // Subscribe before any snapshots are taken, so that no changes are missed in between
+    let mut receiver = events.subscribe();
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            let mut subscription = Subscription::default();
+            loop {
+                let reply = select! {
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Text(text))) => {
+                            handle_client_message(&text, &mut subscription, &tfl, stale_after_secs)
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        // Pings are answered automatically, and we don't accept binary messages
+                        Some(Ok(_)) => None,
+                        Some(Err(err)) => return Err(err),
+                    },
+                    event = receiver.recv() => match event {
+                        Ok(StatusEvent::Line(transition))
+                            if subscription
+                                .includes_line(&transition.line, transition.mode.as_deref()) =>
+                        {
+                            Some(ServerMessage::Line(transition.into()))
+                        }
+                        Ok(StatusEvent::Station(transition))
+                            if subscription.includes_station(&transition.station) =>
+                        {
+                            Some(ServerMessage::Station(transition.into()))
+                        }
+                        Ok(_) => None,
+                        Err(RecvError::Closed) => break,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("WebSocket fell behind, skipped {} events", skipped);
+                            Some(ServerMessage::Error {
+                                message: format!("Missed {} events, resubscribe to resync", skipped),
+                            })
+                        }
+                    },
+                    _ = &mut shutdown => break,
+                };
+                if let Some(reply) = reply {
+                    let text = serde_json::to_string(&reply).unwrap();
+                    stream.send(Message::Text(text)).await?;
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+fn handle_client_message(
+    text: &str,
+    subscription: &mut Subscription,
+    tfl: &Tfl,
+    stale_after_secs: u64,
+) -> Option<ServerMessage> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(err) => {
+            return Some(ServerMessage::Error {
+                message: format!("Invalid message: {}", err),
+            })
+        }
+    };
+    match message {
+        ClientMessage::Subscribe(filter) => {
+            subscription.add(&filter);
+            // Only send the status of what was just subscribed to, so that other subscriptions on
+            // the same connection aren't repeated
+            let mut added = Subscription::default();
+            added.add(&filter);
+            // Until the first poll completes there's nothing to send, and changes will follow
+            let current = tfl.current_status()?;
+            Some(ServerMessage::Snapshot(current_status_response(
+                &current,
+                stale_after_secs,
+                |line, status| added.includes_line(line, Some(&status.mode)),
+                |station| added.includes_station(station),
+            )))
+        }
+        ClientMessage::Unsubscribe(filter) => {
+            subscription.remove(&filter);
+            None
+        }
+    }
+}
@@ -0,0 +1,55 @@
+/// A raw history row for either a line or a station.
+#[derive(Debug, sqlx::FromRow)]
+pub struct HistoryRow {
+    pub key: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub data: Vec<u8>,
+}
+
+/// A run of adjacent rows for the same line or station which have the same status, and can be
+/// collapsed into a single row.
+///
+/// The last row in the run is kept (so that the open row keeps the latest data that the poller
+/// compares against), and has its start time moved back to the start of the run.
+#[derive(Debug)]
+pub struct MergeRun {
+    pub key: String,
+    pub start_time: i64,
+    pub kept_start_time: i64,
+    pub removed_start_times: Vec<i64>,
+}
+
+/// Rows are treated as adjacent if the gap between them is at most this many seconds, since the
+/// old row's end time and the new row's start time may be taken either side of a second boundary.
+pub const ADJACENT_ROW_TOLERANCE_SECS: i64 = 1;
+
+/// Finds the runs of rows which can be merged. `rows` must be sorted by key and then start time.
+pub fn find_merge_runs<F>(rows: &[HistoryRow], same_status: F) -> Vec<MergeRun>
+where
+    F: Fn(&str, &[u8], &[u8]) -> bool,
+{
+    let mut runs = Vec::new();
+    let mut current: Option<MergeRun> = None;
+    for pair in rows.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        let adjacent = prev.key == next.key
+            && prev
+                .end_time
+                .is_some_and(|end| next.start_time - end <= ADJACENT_ROW_TOLERANCE_SECS);
+        if adjacent && same_status(&prev.key, &prev.data, &next.data) {
+            let run = current.get_or_insert_with(|| MergeRun {
+                key: prev.key.clone(),
+                start_time: prev.start_time,
+                kept_start_time: prev.start_time,
+                removed_start_times: Vec::new(),
+            });
+            run.removed_start_times.push(run.kept_start_time);
+            run.kept_start_time = next.start_time;
+        } else if let Some(run) = current.take() {
+            runs.push(run);
+        }
+    }
+    runs.extend(current);
+    runs
+}
@@ -0,0 +1,243 @@
+use std::fmt;
+
+use super::migrations::MigrationError;
+
+#[derive(Debug)]
+pub enum InitializationError {
+    Io(std::io::Error),
+    Sqlx(sqlx::Error),
+    Migration(MigrationError),
+}
+
+impl fmt::Display for InitializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitializationError::Io(err) => {
+                write!(f, "failed to create the store directory: {}", err)
+            }
+            InitializationError::Sqlx(err) => write!(f, "database error: {}", err),
+            InitializationError::Migration(err) => {
+                write!(f, "failed to migrate the schema: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InitializationError {}
+
+impl From<std::io::Error> for InitializationError {
+    fn from(err: std::io::Error) -> Self {
+        InitializationError::Io(err)
+    }
+}
+
+impl From<sqlx::Error> for InitializationError {
+    fn from(err: sqlx::Error) -> Self {
+        InitializationError::Sqlx(err)
+    }
+}
+
+impl From<MigrationError> for InitializationError {
+    fn from(err: MigrationError) -> Self {
+        InitializationError::Migration(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectionError {
+    Sqlx(sqlx::Error),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Sqlx(err) => write!(f, "database error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+impl From<sqlx::Error> for ConnectionError {
+    fn from(err: sqlx::Error) -> Self {
+        ConnectionError::Sqlx(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum GetStatusError {
+    Sqlx(sqlx::Error),
+    InvalidData(String),
+}
+
+impl fmt::Display for GetStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetStatusError::Sqlx(err) => write!(f, "database error: {}", err),
+            GetStatusError::InvalidData(message) => {
+                write!(f, "invalid data in the store: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GetStatusError {}
+
+impl From<sqlx::Error> for GetStatusError {
+    fn from(err: sqlx::Error) -> Self {
+        GetStatusError::Sqlx(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum SetStatusError {
+    Sqlx(sqlx::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SetStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetStatusError::Sqlx(err) => write!(f, "database error: {}", err),
+            SetStatusError::Json(err) => write!(f, "invalid status JSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SetStatusError {}
+
+impl From<sqlx::Error> for SetStatusError {
+    fn from(err: sqlx::Error) -> Self {
+        SetStatusError::Sqlx(err)
+    }
+}
+
+impl From<serde_json::Error> for SetStatusError {
+    fn from(err: serde_json::Error) -> Self {
+        SetStatusError::Json(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum MaintenanceError {
+    Sqlx(sqlx::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for MaintenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceError::Sqlx(err) => write!(f, "database error: {}", err),
+            MaintenanceError::Json(err) => write!(f, "invalid status JSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MaintenanceError {}
+
+impl From<sqlx::Error> for MaintenanceError {
+    fn from(err: sqlx::Error) -> Self {
+        MaintenanceError::Sqlx(err)
+    }
+}
+
+impl From<serde_json::Error> for MaintenanceError {
+    fn from(err: serde_json::Error) -> Self {
+        MaintenanceError::Json(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    Sqlx(sqlx::Error),
+    Json(serde_json::Error),
+    InvalidData(String),
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Sqlx(err) => write!(f, "database error: {}", err),
+            WebhookError::Json(err) => write!(f, "invalid webhook JSON: {}", err),
+            WebhookError::InvalidData(message) => {
+                write!(f, "invalid data in the store: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+impl From<sqlx::Error> for WebhookError {
+    fn from(err: sqlx::Error) -> Self {
+        WebhookError::Sqlx(err)
+    }
+}
+
+impl From<serde_json::Error> for WebhookError {
+    fn from(err: serde_json::Error) -> Self {
+        WebhookError::Json(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum SubscriptionError {
+    Sqlx(sqlx::Error),
+    Json(serde_json::Error),
+    InvalidData(String),
+}
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionError::Sqlx(err) => write!(f, "database error: {}", err),
+            SubscriptionError::Json(err) => write!(f, "invalid subscription JSON: {}", err),
+            SubscriptionError::InvalidData(message) => {
+                write!(f, "invalid data in the store: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SubscriptionError {}
+
+impl From<sqlx::Error> for SubscriptionError {
+    fn from(err: sqlx::Error) -> Self {
+        SubscriptionError::Sqlx(err)
+    }
+}
+
+impl From<serde_json::Error> for SubscriptionError {
+    fn from(err: serde_json::Error) -> Self {
+        SubscriptionError::Json(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum PushError {
+    Sqlx(sqlx::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushError::Sqlx(err) => write!(f, "database error: {}", err),
+            PushError::Json(err) => write!(f, "invalid push subscription JSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PushError {}
+
+impl From<sqlx::Error> for PushError {
+    fn from(err: sqlx::Error) -> Self {
+        PushError::Sqlx(err)
+    }
+}
+
+impl From<serde_json::Error> for PushError {
+    fn from(err: serde_json::Error) -> Self {
+        PushError::Json(err)
+    }
+}
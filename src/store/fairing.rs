@@ -4,6 +4,7 @@ use rocket::{
 };
 
 use super::Store;
+use crate::config::Config;
 
 pub struct StoreFairing;
 
@@ -23,7 +24,8 @@ impl Fairing for StoreFairing {
     }
 
     async fn on_ignite(&self, rocket: Rocket<rocket::Build>) -> Result {
-        match Store::new().await {
+        let config = rocket.state::<Config>().unwrap();
+        match Store::new(&config.store).await {
             Ok(store) => Ok(rocket.manage(store)),
             Err(e) => {
                 log::error!("Failed to initialize store: {:?}", e);
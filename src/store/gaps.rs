@@ -0,0 +1,21 @@
+use super::parsed::to_date_time;
+use super::GetStatusError;
+use crate::types::CollectionGap;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct CollectionGapRow {
+    pub target: String,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+impl TryFrom<CollectionGapRow> for CollectionGap {
+    type Error = GetStatusError;
+
+    fn try_from(row: CollectionGapRow) -> Result<Self, Self::Error> {
+        Ok(CollectionGap {
+            start_time: to_date_time(&row.target, "start", row.start_time)?,
+            end_time: Some(to_date_time(&row.target, "end", row.end_time)?),
+        })
+    }
+}
@@ -0,0 +1,64 @@
+pub mod postgres;
+pub mod sqlite;
+
+use std::fmt;
+
+/// A single forward-only schema change. Migrations are applied in ascending version order and
+/// each version is recorded in the `schema_migrations` table once it has been applied.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// Works out which of `migrations` still need to be applied to a database at `current_version`.
+///
+/// Refuses to continue if the database has been migrated by a newer build than this one, since
+/// we can't know whether the schema is still compatible.
+pub fn pending(
+    migrations: &'static [Migration],
+    current_version: Option<i64>,
+) -> Result<&'static [Migration], MigrationError> {
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+    let current = current_version.unwrap_or(0);
+    if current > latest {
+        return Err(MigrationError::SchemaTooNew {
+            database_version: current,
+            supported_version: latest,
+        });
+    }
+    Ok(&migrations[migrations.partition_point(|m| m.version <= current)..])
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlx(sqlx::Error),
+    SchemaTooNew {
+        database_version: i64,
+        supported_version: i64,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlx(err) => write!(f, "database error: {}", err),
+            MigrationError::SchemaTooNew {
+                database_version,
+                supported_version,
+            } => write!(
+                f,
+                "the database is at schema version {}, but this build only supports up to {}",
+                database_version, supported_version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(err: sqlx::Error) -> Self {
+        MigrationError::Sqlx(err)
+    }
+}
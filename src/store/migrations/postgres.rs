@@ -0,0 +1,205 @@
+use sqlx::{Acquire, PgPool};
+use time::OffsetDateTime;
+
+use super::{Migration, MigrationError};
+
+/// Arbitrary key for the advisory lock held while migrating, so that several instances starting
+/// against the same database don't race each other.
+const MIGRATION_LOCK_ID: i64 = 0x5345_5645_524d;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create line and station history tables",
+        sql: "
+            CREATE TABLE IF NOT EXISTS line_history (
+                line TEXT NOT NULL,
+                start_time BIGINT NOT NULL,
+                end_time BIGINT,
+                data BYTEA NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS station_history (
+                station_id TEXT NOT NULL,
+                start_time BIGINT NOT NULL,
+                end_time BIGINT,
+                data BYTEA NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_line_history_times ON line_history (start_time, end_time);
+            CREATE INDEX IF NOT EXISTS idx_station_history_times ON station_history (start_time, end_time);
+            CREATE INDEX IF NOT EXISTS idx_line_history_open ON line_history (line) WHERE end_time IS NULL;
+            CREATE INDEX IF NOT EXISTS idx_station_history_open ON station_history (station_id) WHERE end_time IS NULL;
+        ",
+    },
+    Migration {
+        version: 2,
+        description: "Store parsed line and station statuses",
+        sql: "
+            ALTER TABLE line_history ADD COLUMN mode TEXT;
+            ALTER TABLE line_history ADD COLUMN parsed BOOLEAN NOT NULL DEFAULT FALSE;
+            ALTER TABLE line_history ADD COLUMN parser_version BIGINT;
+            ALTER TABLE station_history ADD COLUMN parsed BOOLEAN NOT NULL DEFAULT FALSE;
+            ALTER TABLE station_history ADD COLUMN parser_version BIGINT;
+            CREATE TABLE line_history_status (
+                line TEXT NOT NULL,
+                start_time BIGINT NOT NULL,
+                position BIGINT NOT NULL,
+                status TEXT NOT NULL,
+                reason TEXT,
+                PRIMARY KEY (line, start_time, position)
+            );
+            CREATE TABLE station_history_status (
+                station_id TEXT NOT NULL,
+                start_time BIGINT NOT NULL,
+                position BIGINT NOT NULL,
+                status TEXT NOT NULL,
+                description TEXT NOT NULL,
+                PRIMARY KEY (station_id, start_time, position)
+            );
+            CREATE INDEX idx_line_history_mode ON line_history (mode);
+            CREATE INDEX idx_line_history_status_status ON line_history_status (status);
+            CREATE INDEX idx_station_history_status_status ON station_history_status (status);
+        ",
+    },
+    Migration {
+        version: 3,
+        description: "Add webhooks and failed webhook deliveries",
+        sql: "
+            CREATE TABLE webhooks (
+                id BIGSERIAL PRIMARY KEY,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                lines TEXT NOT NULL,
+                modes TEXT NOT NULL,
+                stations TEXT NOT NULL,
+                min_line_severity TEXT,
+                min_station_severity TEXT,
+                created_at BIGINT NOT NULL
+            );
+            CREATE TABLE webhook_dead_letters (
+                id BIGSERIAL PRIMARY KEY,
+                webhook_id BIGINT,
+                url TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                error TEXT NOT NULL,
+                attempts BIGINT NOT NULL,
+                failed_at BIGINT NOT NULL
+            );
+            CREATE INDEX idx_webhook_dead_letters_failed_at ON webhook_dead_letters (failed_at);
+        ",
+    },
+    Migration {
+        version: 4,
+        description: "Add alert subscriptions",
+        sql: "
+            CREATE TABLE subscriptions (
+                id BIGSERIAL PRIMARY KEY,
+                name TEXT NOT NULL,
+                lines TEXT NOT NULL,
+                min_severity TEXT NOT NULL,
+                active_from BIGINT,
+                active_until BIGINT,
+                days TEXT NOT NULL,
+                target TEXT NOT NULL,
+                created_at BIGINT NOT NULL
+            );
+        ",
+    },
+    Migration {
+        version: 5,
+        description: "Add Web Push keys and subscriptions",
+        sql: "
+            CREATE TABLE vapid_keys (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                private_key BYTEA NOT NULL,
+                created_at BIGINT NOT NULL
+            );
+            CREATE TABLE push_subscriptions (
+                endpoint TEXT PRIMARY KEY,
+                p256dh TEXT NOT NULL,
+                auth TEXT NOT NULL,
+                lines TEXT NOT NULL,
+                stations TEXT NOT NULL,
+                min_line_severity TEXT,
+                created_at BIGINT NOT NULL
+            );
+        ",
+    },
+    Migration {
+        version: 6,
+        description: "Store the validity periods and disruption details of line statuses",
+        sql: "
+            ALTER TABLE line_history_status ADD COLUMN validity_periods TEXT;
+            ALTER TABLE line_history_status ADD COLUMN disruption TEXT;
+        ",
+    },
+    Migration {
+        version: 7,
+        description: "Record the last successful polls and the gaps in collecting status",
+        sql: "
+            CREATE TABLE collector_polls (
+                target TEXT PRIMARY KEY,
+                last_success BIGINT NOT NULL
+            );
+            CREATE TABLE collector_gaps (
+                target TEXT NOT NULL,
+                start_time BIGINT NOT NULL,
+                end_time BIGINT NOT NULL,
+                PRIMARY KEY (target, start_time)
+            );
+        ",
+    },
+];
+
+pub async fn run(pool: &PgPool) -> Result<(), MigrationError> {
+    let mut connection = pool.acquire().await?;
+
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut *connection)
+        .await?;
+    let result = run_locked(&mut connection).await;
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut *connection)
+        .await?;
+    result
+}
+
+async fn run_locked(connection: &mut sqlx::PgConnection) -> Result<(), MigrationError> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at BIGINT NOT NULL
+        )",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    let current_version: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+            .fetch_one(&mut *connection)
+            .await?;
+    let pending = super::pending(MIGRATIONS, current_version)?;
+
+    for migration in pending {
+        log::info!(
+            "Applying migration {}: {}",
+            migration.version,
+            migration.description
+        );
+        let mut txn = connection.begin().await?;
+        sqlx::raw_sql(migration.sql).execute(&mut *txn).await?;
+        sqlx::query(
+            "INSERT INTO schema_migrations (version, description, applied_at) VALUES ($1, $2, $3)",
+        )
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .execute(&mut *txn)
+        .await?;
+        txn.commit().await?;
+    }
+
+    Ok(())
+}
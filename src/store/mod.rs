@@ -1,25 +1,280 @@
+mod compaction;
+mod error;
 mod fairing;
+mod gaps;
+mod migrations;
+mod parsed;
+mod postgres;
+mod push;
+mod sql;
 mod sqlite;
+mod subscriptions;
+mod webhooks;
 
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
 use rocket::request::FromRequest;
+use serde_json::Value;
+use time::OffsetDateTime;
 
+use self::postgres::PostgresStore;
 use self::sqlite::SqliteStore;
-pub use self::sqlite::{
-    ConnectionError, InitializationError, SetStatusError, SqliteConnection as StoreConnection,
+use crate::config::{StoreConfig, WebhookTarget};
+use crate::types::{
+    CollectionGap, LineState, LineStatusHistoryEntry, LineTransition, PushSubscription,
+    StationState, StationStatusHistoryEntry, StationTransition, Subscription,
 };
 
+pub use self::error::{
+    ConnectionError, GetStatusError, InitializationError, MaintenanceError, PushError,
+    SetStatusError, SubscriptionError, WebhookError,
+};
 pub use self::fairing::StoreFairing;
 
+/// Decides whether a line's status has changed enough to start a new history row.
+pub type ShouldUpdateLine = dyn Fn(&Value, &Value) -> bool + Send + Sync;
+
+/// Decides whether a station's disruptions have changed enough to start a new history row.
+pub type ShouldUpdateStation = dyn Fn(&[Value], &[Value]) -> bool + Send + Sync;
+
+/// Decides whether two stored statuses for a line are equivalent, so adjacent rows can be merged.
+pub type SameLineStatus = dyn Fn(&str, &Value, &Value) -> bool + Send + Sync;
+
+/// Decides whether two stored statuses for a station are equivalent, so adjacent rows can be merged.
+pub type SameStationStatus = dyn Fn(&str, &[Value], &[Value]) -> bool + Send + Sync;
+
+/// Reduces a stored line status to the fields that we actually use.
+pub type StripLineStatus = dyn Fn(&Value) -> Value + Send + Sync;
+
+/// Reduces a stored station status to the fields that we actually use.
+pub type StripStationStatus = dyn Fn(&[Value]) -> Vec<Value> + Send + Sync;
+
+/// Restricts which line history periods are returned. Empty lists match everything.
+#[derive(Debug, Default)]
+pub struct LineHistoryFilter {
+    pub lines: Vec<String>,
+    pub modes: Vec<String>,
+    /// Only include periods where at least one status is at least this severe.
+    pub min_severity: Option<LineState>,
+}
+
+/// Restricts which station history periods are returned. Empty lists match everything.
+#[derive(Debug, Default)]
+pub struct StationHistoryFilter {
+    pub stations: Vec<String>,
+    /// Only include periods where at least one disruption is at least this severe.
+    pub min_severity: Option<StationState>,
+}
+
+/// A webhook registered through the API.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: i64,
+    pub target: WebhookTarget,
+}
+
+/// A webhook delivery which was given up on after running out of retries.
+#[derive(Debug, Clone)]
+pub struct WebhookDeadLetter {
+    /// The registered webhook, or `None` if it came from the config.
+    pub webhook_id: Option<i64>,
+    pub url: String,
+    pub payload: String,
+    pub error: String,
+    pub attempts: i64,
+    pub failed_at: OffsetDateTime,
+}
+
+/// An alert subscription registered through the API.
+#[derive(Debug, Clone)]
+pub struct StoredSubscription {
+    pub id: i64,
+    pub subscription: Subscription,
+}
+
+/// A storage backend, which hands out connections to the underlying database.
+#[rocket::async_trait]
+pub trait Backend: Send + Sync {
+    async fn get_connection(&self) -> Result<StoreConnection, ConnectionError>;
+
+    async fn shutdown(&self);
+}
+
+/// The operations that can be performed against a single connection to a storage backend.
+#[rocket::async_trait]
+pub trait Connection: Send {
+    async fn get_line_status_history(
+        &mut self,
+        start_time: OffsetDateTime,
+        end_time: OffsetDateTime,
+        filter: &LineHistoryFilter,
+    ) -> Result<HashMap<String, Vec<LineStatusHistoryEntry>>, GetStatusError>;
+
+    /// Gets the status of each line at `time`, or the current status if `time` is `None`.
+    async fn get_line_status_at(
+        &mut self,
+        time: Option<OffsetDateTime>,
+    ) -> Result<HashMap<String, LineStatusHistoryEntry>, GetStatusError>;
+
+    /// Records the latest status of each line, closing the open row and starting a new one for
+    /// each line where `should_update` returns true for the old and new status. Returns the
+    /// lines which changed.
+    async fn set_line_status(
+        &mut self,
+        status_by_line: HashMap<String, Value>,
+        should_update: &ShouldUpdateLine,
+    ) -> Result<Vec<LineTransition>, SetStatusError>;
+
+    async fn get_station_status_history(
+        &mut self,
+        start_time: OffsetDateTime,
+        end_time: OffsetDateTime,
+        filter: &StationHistoryFilter,
+    ) -> Result<HashMap<String, Vec<StationStatusHistoryEntry>>, GetStatusError>;
+
+    /// Gets the disruptions at each station at `time`, or the current disruptions if `time` is
+    /// `None`. Stations without any disruptions are omitted.
+    async fn get_station_status_at(
+        &mut self,
+        time: Option<OffsetDateTime>,
+    ) -> Result<HashMap<String, StationStatusHistoryEntry>, GetStatusError>;
+
+    /// Records the latest disruptions at each station. Stations that are no longer reported have
+    /// their open row closed. Returns the stations which changed.
+    async fn set_station_status(
+        &mut self,
+        status_by_station: HashMap<String, Vec<Value>>,
+        should_update: &ShouldUpdateStation,
+    ) -> Result<Vec<StationTransition>, SetStatusError>;
+
+    /// Records a successful poll of `target`, after recording a gap since the previous one if
+    /// that was more than `gap_after` ago. Returns the gap, if there was one.
+    async fn record_poll(
+        &mut self,
+        target: &str,
+        time: OffsetDateTime,
+        gap_after: time::Duration,
+    ) -> Result<Option<CollectionGap>, SetStatusError>;
+
+    /// When `target` was last polled successfully, or `None` if it never has been.
+    async fn get_last_poll(
+        &mut self,
+        target: &str,
+    ) -> Result<Option<OffsetDateTime>, GetStatusError>;
+
+    /// Gets the recorded gaps in collecting `target` which overlap the period from `start_time`
+    /// to `end_time`, oldest first.
+    async fn get_collection_gaps(
+        &mut self,
+        target: &str,
+        start_time: OffsetDateTime,
+        end_time: OffsetDateTime,
+    ) -> Result<Vec<CollectionGap>, GetStatusError>;
+
+    /// Deletes line and station history rows which ended before `before`, returning the number
+    /// of rows deleted. Collection gaps which ended before then are deleted too.
+    async fn purge_history(&mut self, before: OffsetDateTime) -> Result<u64, MaintenanceError>;
+
+    /// Collapses adjacent line history rows with equivalent statuses, returning the number of rows
+    /// removed.
+    async fn merge_line_history(
+        &mut self,
+        same_status: &SameLineStatus,
+    ) -> Result<u64, MaintenanceError>;
+
+    /// Collapses adjacent station history rows with equivalent statuses, returning the number of
+    /// rows removed.
+    async fn merge_station_history(
+        &mut self,
+        same_status: &SameStationStatus,
+    ) -> Result<u64, MaintenanceError>;
+
+    /// Rewrites the data of closed line history rows, returning the number of rows changed. Open
+    /// rows are left alone because the poller compares against their full data.
+    async fn strip_line_history(
+        &mut self,
+        strip: &StripLineStatus,
+    ) -> Result<u64, MaintenanceError>;
+
+    /// Rewrites the data of closed station history rows, returning the number of rows changed.
+    async fn strip_station_history(
+        &mut self,
+        strip: &StripStationStatus,
+    ) -> Result<u64, MaintenanceError>;
+
+    /// Reclaims space freed by the other maintenance operations.
+    async fn vacuum(&mut self) -> Result<(), MaintenanceError>;
+
+    async fn get_webhooks(&mut self) -> Result<Vec<Webhook>, WebhookError>;
+
+    async fn add_webhook(&mut self, target: &WebhookTarget) -> Result<Webhook, WebhookError>;
+
+    /// Deletes a registered webhook, returning whether it existed.
+    async fn delete_webhook(&mut self, id: i64) -> Result<bool, WebhookError>;
+
+    async fn add_webhook_dead_letter(
+        &mut self,
+        dead_letter: &WebhookDeadLetter,
+    ) -> Result<(), WebhookError>;
+
+    /// Gets the most recent failed webhook deliveries, newest first.
+    async fn get_webhook_dead_letters(
+        &mut self,
+        limit: i64,
+    ) -> Result<Vec<WebhookDeadLetter>, WebhookError>;
+
+    async fn get_subscriptions(&mut self) -> Result<Vec<StoredSubscription>, SubscriptionError>;
+
+    async fn get_subscription(
+        &mut self,
+        id: i64,
+    ) -> Result<Option<StoredSubscription>, SubscriptionError>;
+
+    async fn add_subscription(
+        &mut self,
+        subscription: &Subscription,
+    ) -> Result<StoredSubscription, SubscriptionError>;
+
+    /// Replaces a subscription, returning whether it existed.
+    async fn update_subscription(
+        &mut self,
+        id: i64,
+        subscription: &Subscription,
+    ) -> Result<bool, SubscriptionError>;
+
+    /// Deletes a subscription, returning whether it existed.
+    async fn delete_subscription(&mut self, id: i64) -> Result<bool, SubscriptionError>;
+
+    /// Stores `private_key` as the VAPID key if there isn't one already, and returns the stored
+    /// key.
+    async fn get_or_insert_vapid_key(&mut self, private_key: &[u8]) -> Result<Vec<u8>, PushError>;
+
+    async fn get_push_subscriptions(&mut self) -> Result<Vec<PushSubscription>, PushError>;
+
+    /// Adds a push subscription, or replaces the one with the same endpoint.
+    async fn set_push_subscription(
+        &mut self,
+        subscription: &PushSubscription,
+    ) -> Result<(), PushError>;
+
+    /// Deletes a push subscription, returning whether it existed.
+    async fn delete_push_subscription(&mut self, endpoint: &str) -> Result<bool, PushError>;
+}
+
 #[derive(Clone)]
 pub struct Store {
-    inner: Arc<SqliteStore>,
+    inner: Arc<dyn Backend>,
 }
 
 impl Store {
-    pub async fn new() -> Result<Self, InitializationError> {
-        let inner = Arc::new(SqliteStore::new().await?);
+    pub async fn new(config: &StoreConfig) -> Result<Self, InitializationError> {
+        let inner: Arc<dyn Backend> = match config {
+            StoreConfig::Sqlite(config) => Arc::new(SqliteStore::new(config).await?),
+            StoreConfig::Memory => Arc::new(SqliteStore::in_memory().await?),
+            StoreConfig::Postgres(config) => Arc::new(PostgresStore::new(config).await?),
+        };
         Ok(Store { inner })
     }
 
@@ -32,6 +287,32 @@ impl Store {
     }
 }
 
+pub struct StoreConnection {
+    inner: Box<dyn Connection>,
+}
+
+impl StoreConnection {
+    fn new(inner: impl Connection + 'static) -> Self {
+        StoreConnection {
+            inner: Box::new(inner),
+        }
+    }
+}
+
+impl Deref for StoreConnection {
+    type Target = dyn Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.inner
+    }
+}
+
+impl DerefMut for StoreConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.inner
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for StoreConnection {
     type Error = ConnectionError;
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use time::OffsetDateTime;
+
+use super::compaction::ADJACENT_ROW_TOLERANCE_SECS;
+use super::GetStatusError;
+use crate::tfl;
+use crate::types::{
+    LineState, LineStatus, LineStatusHistoryEntry, StationState, StationStatus,
+    StationStatusHistoryEntry,
+};
+
+/// The parsed form of a line history row, which is stored alongside the raw data.
+pub struct ParsedLine {
+    pub mode: String,
+    pub statuses: Vec<LineStatus>,
+}
+
+pub fn parse_line(line: &str, data: &Value) -> Option<ParsedLine> {
+    let (metadata, statuses) = tfl::try_parse_line_status(line, data)?;
+    Some(ParsedLine {
+        mode: metadata.mode,
+        statuses,
+    })
+}
+
+pub fn parse_station(station: &str, data: &[Value]) -> Option<Vec<StationStatus>> {
+    tfl::try_parse_station_status(station, data)
+}
+
+/// A line history row joined with one of its parsed statuses (if it has any).
+#[derive(Debug, sqlx::FromRow)]
+pub struct LineStatusRow {
+    pub line: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub mode: Option<String>,
+    pub status: Option<String>,
+    pub reason: Option<String>,
+    /// JSON encoded, as are the disruption details.
+    pub validity_periods: Option<String>,
+    pub disruption: Option<String>,
+}
+
+/// A station history row joined with one of its parsed statuses (if it has any).
+#[derive(Debug, sqlx::FromRow)]
+pub struct StationStatusRow {
+    pub station_id: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub status: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Gets the name that a state is stored as, which matches how it's serialized in the API.
+pub fn state_name<S: Serialize>(state: S) -> String {
+    match serde_json::to_value(state) {
+        Ok(Value::String(name)) => name,
+        other => panic!("States should serialize to strings, got {:?}", other),
+    }
+}
+
+/// Gets the names of all the line states which are disruptions at least as severe as
+/// `min_severity`, leaving out lines closing for the night.
+pub fn line_states_at_least(min_severity: LineState) -> Vec<String> {
+    LineState::ALL
+        .iter()
+        .filter(|state| state.is_disruption_at_least(min_severity))
+        .map(state_name)
+        .collect()
+}
+
+/// Gets the names of all the station states which are at least as severe as `min_severity`.
+pub fn station_states_at_least(min_severity: StationState) -> Vec<String> {
+    StationState::ALL
+        .iter()
+        .filter(|state| **state <= min_severity)
+        .map(state_name)
+        .collect()
+}
+
+/// Encodes the details of a parsed status which are stored as JSON.
+pub fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("Parsed statuses should serialize to JSON")
+}
+
+fn parse_state<S: DeserializeOwned>(name: &str, fallback: S) -> S {
+    serde_json::from_value(Value::String(name.to_string())).unwrap_or(fallback)
+}
+
+/// Builds the history for each line from rows ordered by line, start time and status position,
+/// merging adjacent periods where the statuses didn't change.
+pub fn group_line_rows(
+    rows: Vec<LineStatusRow>,
+) -> Result<HashMap<String, Vec<LineStatusHistoryEntry>>, GetStatusError> {
+    let mut result = HashMap::<String, Vec<LineStatusHistoryEntry>>::new();
+    let mut current: Option<(String, i64, LineStatusHistoryEntry)> = None;
+    for row in rows {
+        let is_same_row = current
+            .as_ref()
+            .is_some_and(|(line, start, _)| *line == row.line && *start == row.start_time);
+        if !is_same_row {
+            if let Some((line, _, entry)) = current.take() {
+                push_line_entry(result.entry(line).or_default(), entry);
+            }
+            let entry = LineStatusHistoryEntry {
+                start_time: to_date_time(&row.line, "start", row.start_time)?,
+                end_time: row
+                    .end_time
+                    .map(|t| to_date_time(&row.line, "end", t))
+                    .transpose()?,
+                mode: row.mode.unwrap_or_default(),
+                statuses: Vec::new(),
+            };
+            current = Some((row.line, row.start_time, entry));
+        }
+        if let (Some((_, _, entry)), Some(status)) = (current.as_mut(), row.status) {
+            entry.statuses.push(LineStatus {
+                status: parse_state(&status, LineState::Other),
+                reason: row.reason,
+                validity_periods: row
+                    .validity_periods
+                    .and_then(|periods| serde_json::from_str(&periods).ok())
+                    .unwrap_or_default(),
+                disruption: row
+                    .disruption
+                    .and_then(|disruption| serde_json::from_str(&disruption).ok()),
+            });
+        }
+    }
+    if let Some((line, _, entry)) = current {
+        push_line_entry(result.entry(line).or_default(), entry);
+    }
+    Ok(result)
+}
+
+fn push_line_entry(entries: &mut Vec<LineStatusHistoryEntry>, entry: LineStatusHistoryEntry) {
+    if let Some(last) = entries.last_mut() {
+        if last.statuses == entry.statuses && is_adjacent(last.end_time, entry.start_time) {
+            last.end_time = entry.end_time;
+            last.mode = entry.mode;
+            return;
+        }
+    }
+    entries.push(entry);
+}
+
+/// Builds the history for each station from rows ordered by station, start time and status
+/// position, merging adjacent periods where the statuses didn't change.
+pub fn group_station_rows(
+    rows: Vec<StationStatusRow>,
+) -> Result<HashMap<String, Vec<StationStatusHistoryEntry>>, GetStatusError> {
+    let mut result = HashMap::<String, Vec<StationStatusHistoryEntry>>::new();
+    let mut current: Option<(String, i64, StationStatusHistoryEntry)> = None;
+    for row in rows {
+        let is_same_row = current.as_ref().is_some_and(|(station, start, _)| {
+            *station == row.station_id && *start == row.start_time
+        });
+        if !is_same_row {
+            if let Some((station, _, entry)) = current.take() {
+                push_station_entry(result.entry(station).or_default(), entry);
+            }
+            let entry = StationStatusHistoryEntry {
+                start_time: to_date_time(&row.station_id, "start", row.start_time)?,
+                end_time: row
+                    .end_time
+                    .map(|t| to_date_time(&row.station_id, "end", t))
+                    .transpose()?,
+                statuses: Vec::new(),
+            };
+            current = Some((row.station_id, row.start_time, entry));
+        }
+        if let (Some((_, _, entry)), Some(status)) = (current.as_mut(), row.status) {
+            entry.statuses.push(StationStatus {
+                status: parse_state(&status, StationState::Other),
+                description: row.description.unwrap_or_default(),
+            });
+        }
+    }
+    if let Some((station, _, entry)) = current {
+        push_station_entry(result.entry(station).or_default(), entry);
+    }
+    Ok(result)
+}
+
+fn push_station_entry(
+    entries: &mut Vec<StationStatusHistoryEntry>,
+    entry: StationStatusHistoryEntry,
+) {
+    if let Some(last) = entries.last_mut() {
+        if last.statuses == entry.statuses && is_adjacent(last.end_time, entry.start_time) {
+            last.end_time = entry.end_time;
+            return;
+        }
+    }
+    entries.push(entry);
+}
+
+/// Whether a period starting at `start_time` directly follows one ending at `end_time`, rather than
+/// there being a gap (e.g. because the periods in between were filtered out).
+fn is_adjacent(end_time: Option<OffsetDateTime>, start_time: OffsetDateTime) -> bool {
+    end_time.is_some_and(|end| (start_time - end).whole_seconds() <= ADJACENT_ROW_TOLERANCE_SECS)
+}
+
+pub fn to_date_time(
+    key: &str,
+    which: &str,
+    timestamp: i64,
+) -> Result<OffsetDateTime, GetStatusError> {
+    OffsetDateTime::from_unix_timestamp(timestamp).map_err(|_| {
+        GetStatusError::InvalidData(format!("{}: Invalid {} time: {}", key, which, timestamp))
+    })
+}
@@ -0,0 +1,59 @@
+use sqlx::postgres::PgQueryResult;
+use sqlx::{self, Postgres};
+
+use super::migrations;
+use super::sql::{SqlConnection, SqlDatabase};
+use super::{Backend, ConnectionError, InitializationError, StoreConnection};
+use crate::config::PostgresConfig;
+
+/// Arbitrary key for the advisory lock taken while updating status.
+const STATUS_UPDATE_LOCK_ID: i64 = 0x5345_5645_5245;
+
+pub struct PostgresStore {
+    pool: sqlx::PgPool,
+}
+
+impl PostgresStore {
+    pub async fn new(config: &PostgresConfig) -> Result<Self, InitializationError> {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect(&config.url)
+            .await?;
+
+        migrations::postgres::run(&pool).await?;
+        SqlConnection::new(pool.acquire().await?)
+            .backfill_parsed_status()
+            .await?;
+
+        Ok(PostgresStore { pool })
+    }
+}
+
+#[rocket::async_trait]
+impl Backend for PostgresStore {
+    async fn get_connection(&self) -> Result<StoreConnection, ConnectionError> {
+        Ok(StoreConnection::new(SqlConnection::new(
+            self.pool.acquire().await?,
+        )))
+    }
+
+    async fn shutdown(&self) {
+        self.pool.close().await;
+    }
+}
+
+impl SqlDatabase for Postgres {
+    /// Several instances may share one database, so serialise the read-compare-write cycles which
+    /// modify history rows, e.g. to avoid two pollers both opening a new row for the same line.
+    async fn lock_for_update(connection: &mut sqlx::PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(STATUS_UPDATE_LOCK_ID)
+            .execute(connection)
+            .await?;
+        Ok(())
+    }
+
+    fn rows_affected(result: &PgQueryResult) -> u64 {
+        result.rows_affected()
+    }
+}
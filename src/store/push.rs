@@ -0,0 +1,32 @@
+use serde_json::Value;
+
+use super::PushError;
+use crate::types::PushSubscription;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct PushSubscriptionRow {
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+    pub lines: String,
+    pub stations: String,
+    pub min_line_severity: Option<String>,
+}
+
+impl TryFrom<PushSubscriptionRow> for PushSubscription {
+    type Error = PushError;
+
+    fn try_from(row: PushSubscriptionRow) -> Result<Self, Self::Error> {
+        Ok(PushSubscription {
+            endpoint: row.endpoint,
+            p256dh: row.p256dh,
+            auth: row.auth,
+            lines: serde_json::from_str(&row.lines)?,
+            stations: serde_json::from_str(&row.stations)?,
+            min_line_severity: row
+                .min_line_severity
+                .map(|name| serde_json::from_value(Value::String(name)))
+                .transpose()?,
+        })
+    }
+}
@@ -0,0 +1,1041 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::{
+    self, Acquire, ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments,
+    QueryBuilder, Type,
+};
+use time::OffsetDateTime;
+
+use super::compaction::{self, HistoryRow};
+use super::gaps::CollectionGapRow;
+use super::parsed::{self, LineStatusRow, ParsedLine, StationStatusRow};
+use super::push::PushSubscriptionRow;
+use super::subscriptions::{self, SubscriptionRow};
+use super::webhooks::{WebhookDeadLetterRow, WebhookRow};
+use super::{
+    Connection, GetStatusError, LineHistoryFilter, MaintenanceError, PushError, SameLineStatus,
+    SameStationStatus, SetStatusError, ShouldUpdateLine, ShouldUpdateStation, StationHistoryFilter,
+    StoredSubscription, StripLineStatus, StripStationStatus, SubscriptionError, Webhook,
+    WebhookDeadLetter, WebhookError,
+};
+use crate::config::WebhookTarget;
+use crate::tfl;
+use crate::types::{
+    CollectionGap, LineStatusHistoryEntry, LineTransition, PushSubscription, StationStatus,
+    StationStatusHistoryEntry, StationTransition, Subscription,
+};
+
+/// What differs between the databases that the store can use, beyond the types that sqlx maps
+/// for each. The queries are shared, and use `$1` style placeholders which both understand.
+pub trait SqlDatabase: Database {
+    /// Takes whatever lock is needed at the start of a transaction which modifies history rows,
+    /// so that instances sharing the database don't interleave their updates.
+    fn lock_for_update(
+        connection: &mut Self::Connection,
+    ) -> impl Future<Output = Result<(), sqlx::Error>> + Send;
+
+    /// How many rows a statement inserted, updated or deleted.
+    fn rows_affected(result: &Self::QueryResult) -> u64;
+}
+
+/// A connection to either a SQLite or Postgres database.
+pub struct SqlConnection<DB: Database> {
+    connection: PoolConnection<DB>,
+}
+
+#[rocket::async_trait]
+impl<DB> Connection for SqlConnection<DB>
+where
+    DB: SqlDatabase,
+    DB::Arguments: IntoArguments<DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> i64: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> Option<i64>: Encode<'q, DB> + Type<DB>,
+    for<'q> bool: Encode<'q, DB> + Type<DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<&'q str>: Encode<'q, DB> + Type<DB>,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<String>: Encode<'q, DB> + Type<DB>,
+    for<'q> &'q [u8]: Encode<'q, DB> + Type<DB>,
+    for<'q> Vec<u8>: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'r> HistoryRow: FromRow<'r, DB::Row>,
+    for<'r> LineStatusRow: FromRow<'r, DB::Row>,
+    for<'r> StationStatusRow: FromRow<'r, DB::Row>,
+    for<'r> CollectionGapRow: FromRow<'r, DB::Row>,
+    for<'r> WebhookRow: FromRow<'r, DB::Row>,
+    for<'r> WebhookDeadLetterRow: FromRow<'r, DB::Row>,
+    for<'r> SubscriptionRow: FromRow<'r, DB::Row>,
+    for<'r> PushSubscriptionRow: FromRow<'r, DB::Row>,
+{
+    async fn get_line_status_history(
+        &mut self,
+        start_time: OffsetDateTime,
+        end_time: OffsetDateTime,
+        filter: &LineHistoryFilter,
+    ) -> Result<HashMap<String, Vec<LineStatusHistoryEntry>>, GetStatusError> {
+        let mut query = QueryBuilder::<DB>::new(
+            "SELECT h.line, h.start_time, h.end_time, h.mode, s.status, s.reason,
+            s.validity_periods, s.disruption
+            FROM line_history h
+            LEFT JOIN line_history_status s ON s.line = h.line AND s.start_time = h.start_time
+            WHERE h.parsed AND h.start_time <= ",
+        );
+        query.push_bind(end_time.unix_timestamp());
+        query.push(" AND (h.end_time IS NULL OR h.end_time >= ");
+        query.push_bind(start_time.unix_timestamp());
+        query.push(")");
+        Self::push_in_filter(&mut query, "h.line", &filter.lines);
+        Self::push_in_filter(&mut query, "h.mode", &filter.modes);
+        if let Some(min_severity) = filter.min_severity {
+            query.push(
+                " AND EXISTS (SELECT 1 FROM line_history_status f
+                WHERE f.line = h.line AND f.start_time = h.start_time",
+            );
+            Self::push_in_filter(
+                &mut query,
+                "f.status",
+                &parsed::line_states_at_least(min_severity),
+            );
+            query.push(")");
+        }
+        query.push(" ORDER BY h.line, h.start_time, s.position");
+        let rows = query
+            .build_query_as::<LineStatusRow>()
+            .fetch_all(&mut *self.connection)
+            .await?;
+        parsed::group_line_rows(rows)
+    }
+
+    async fn get_line_status_at(
+        &mut self,
+        time: Option<OffsetDateTime>,
+    ) -> Result<HashMap<String, LineStatusHistoryEntry>, GetStatusError> {
+        let mut query = QueryBuilder::<DB>::new(
+            "SELECT h.line, h.start_time, h.end_time, h.mode, s.status, s.reason,
+            s.validity_periods, s.disruption
+            FROM line_history h
+            LEFT JOIN line_history_status s ON s.line = h.line AND s.start_time = h.start_time
+            WHERE h.parsed AND ",
+        );
+        Self::push_point_in_time(&mut query, time);
+        query.push(" ORDER BY h.line, h.start_time, s.position");
+        let rows = query
+            .build_query_as::<LineStatusRow>()
+            .fetch_all(&mut *self.connection)
+            .await?;
+        Ok(parsed::group_line_rows(rows)?
+            .into_iter()
+            .filter_map(|(line, mut entries)| Some((line, entries.pop()?)))
+            .collect())
+    }
+
+    async fn set_line_status(
+        &mut self,
+        status_by_line: HashMap<String, Value>,
+        should_update: &ShouldUpdateLine,
+    ) -> Result<Vec<LineTransition>, SetStatusError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let now = OffsetDateTime::now_utc();
+        let existing = sqlx::query_as::<_, HistoryRow>(
+            "SELECT line AS key, start_time, end_time, data FROM line_history WHERE end_time IS NULL",
+        )
+        .fetch_all(&mut *txn)
+        .await?
+        .into_iter()
+        .map(|entry| (entry.key, entry.data))
+        .collect::<HashMap<_, _>>();
+        let mut transitions = Vec::new();
+        for (line, status) in status_by_line {
+            let previous = if let Some(existing) = existing.get(&line) {
+                let existing = serde_json::from_slice::<Value>(existing)?;
+                if !should_update(&existing, &status) {
+                    continue;
+                }
+                log::info!(
+                    "Found existing row, but it didn't match - updating: {:?}",
+                    line
+                );
+                sqlx::query(
+                    "UPDATE line_history SET end_time = $1 WHERE line = $2 AND end_time IS NULL",
+                )
+                .bind(OffsetDateTime::now_utc().unix_timestamp())
+                .bind(&line)
+                .execute(&mut *txn)
+                .await?;
+                parsed::parse_line(&line, &existing)
+            } else {
+                log::info!("No existing entry: {:?}", line);
+                None
+            };
+            sqlx::query(
+                "INSERT INTO line_history (line, start_time, end_time, data) VALUES ($1, $2, NULL, $3)",
+            )
+            .bind(&line)
+            .bind(now.unix_timestamp())
+            .bind(serde_json::to_vec(&status)?)
+            .execute(&mut *txn)
+            .await?;
+            let current =
+                Self::write_parsed_line_status(&mut txn, &line, now.unix_timestamp(), &status)
+                    .await?;
+            transitions.push(LineTransition {
+                line,
+                time: now,
+                mode: current.as_ref().map(|parsed| parsed.mode.clone()),
+                previous: previous.map(|parsed| parsed.statuses),
+                current: current.map(|parsed| parsed.statuses),
+            });
+        }
+        txn.commit().await?;
+        Ok(transitions)
+    }
+
+    async fn get_station_status_history(
+        &mut self,
+        start_time: OffsetDateTime,
+        end_time: OffsetDateTime,
+        filter: &StationHistoryFilter,
+    ) -> Result<HashMap<String, Vec<StationStatusHistoryEntry>>, GetStatusError> {
+        let mut query = QueryBuilder::<DB>::new(
+            "SELECT h.station_id, h.start_time, h.end_time, s.status, s.description
+            FROM station_history h
+            LEFT JOIN station_history_status s
+                ON s.station_id = h.station_id AND s.start_time = h.start_time
+            WHERE h.parsed AND h.start_time <= ",
+        );
+        query.push_bind(end_time.unix_timestamp());
+        query.push(" AND (h.end_time IS NULL OR h.end_time >= ");
+        query.push_bind(start_time.unix_timestamp());
+        query.push(")");
+        Self::push_in_filter(&mut query, "h.station_id", &filter.stations);
+        if let Some(min_severity) = filter.min_severity {
+            query.push(
+                " AND EXISTS (SELECT 1 FROM station_history_status f
+                WHERE f.station_id = h.station_id AND f.start_time = h.start_time",
+            );
+            Self::push_in_filter(
+                &mut query,
+                "f.status",
+                &parsed::station_states_at_least(min_severity),
+            );
+            query.push(")");
+        }
+        query.push(" ORDER BY h.station_id, h.start_time, s.position");
+        let rows = query
+            .build_query_as::<StationStatusRow>()
+            .fetch_all(&mut *self.connection)
+            .await?;
+        parsed::group_station_rows(rows)
+    }
+
+    async fn get_station_status_at(
+        &mut self,
+        time: Option<OffsetDateTime>,
+    ) -> Result<HashMap<String, StationStatusHistoryEntry>, GetStatusError> {
+        let mut query = QueryBuilder::<DB>::new(
+            "SELECT h.station_id, h.start_time, h.end_time, s.status, s.description
+            FROM station_history h
+            LEFT JOIN station_history_status s
+                ON s.station_id = h.station_id AND s.start_time = h.start_time
+            WHERE h.parsed AND ",
+        );
+        Self::push_point_in_time(&mut query, time);
+        query.push(" ORDER BY h.station_id, h.start_time, s.position");
+        let rows = query
+            .build_query_as::<StationStatusRow>()
+            .fetch_all(&mut *self.connection)
+            .await?;
+        Ok(parsed::group_station_rows(rows)?
+            .into_iter()
+            .filter_map(|(station, mut entries)| Some((station, entries.pop()?)))
+            .collect())
+    }
+
+    async fn set_station_status(
+        &mut self,
+        status_by_station: HashMap<String, Vec<Value>>,
+        should_update: &ShouldUpdateStation,
+    ) -> Result<Vec<StationTransition>, SetStatusError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let now = OffsetDateTime::now_utc();
+        let existing = sqlx::query_as::<_, HistoryRow>(
+            "SELECT station_id AS key, start_time, end_time, data FROM station_history WHERE end_time IS NULL",
+        )
+        .fetch_all(&mut *txn)
+        .await?
+        .into_iter()
+        .map(|entry| (entry.key, entry.data))
+        .collect::<HashMap<_, _>>();
+        let mut transitions = Vec::new();
+        for (station, data) in existing.iter() {
+            if !status_by_station.contains_key(station) {
+                log::info!(
+                    "Existing station row but no current status - setting end time: {:?}",
+                    station
+                );
+                sqlx::query(
+                    "UPDATE station_history SET end_time = $1 WHERE station_id = $2 AND end_time IS NULL",
+                )
+                .bind(OffsetDateTime::now_utc().unix_timestamp())
+                .bind(station)
+                .execute(&mut *txn)
+                .await?;
+                let previous = serde_json::from_slice::<Vec<Value>>(data)?;
+                transitions.push(StationTransition {
+                    station: station.clone(),
+                    time: now,
+                    previous: parsed::parse_station(station, &previous).unwrap_or_default(),
+                    current: vec![],
+                });
+            }
+        }
+        for (station, status) in status_by_station {
+            let previous = if let Some(existing) = existing.get(&station) {
+                let existing = serde_json::from_slice::<Vec<Value>>(existing)?;
+                if !should_update(&existing, &status) {
+                    continue;
+                }
+                log::info!(
+                    "Found existing station row, but it didn't match - updating: {:?}",
+                    station
+                );
+                sqlx::query(
+                    "UPDATE station_history SET end_time = $1 WHERE station_id = $2 AND end_time IS NULL",
+                )
+                .bind(OffsetDateTime::now_utc().unix_timestamp())
+                .bind(&station)
+                .execute(&mut *txn)
+                .await?;
+                parsed::parse_station(&station, &existing).unwrap_or_default()
+            } else {
+                log::info!("No existing station entry: {:?}", station);
+                vec![]
+            };
+            sqlx::query(
+                "INSERT INTO station_history (station_id, start_time, end_time, data) VALUES ($1, $2, NULL, $3)",
+            )
+            .bind(&station)
+            .bind(now.unix_timestamp())
+            .bind(serde_json::to_vec(&status)?)
+            .execute(&mut *txn)
+            .await?;
+            let current = Self::write_parsed_station_status(
+                &mut txn,
+                &station,
+                now.unix_timestamp(),
+                &status,
+            )
+            .await?;
+            transitions.push(StationTransition {
+                station,
+                time: now,
+                previous,
+                current: current.unwrap_or_default(),
+            });
+        }
+        txn.commit().await?;
+        Ok(transitions)
+    }
+
+    async fn record_poll(
+        &mut self,
+        target: &str,
+        time: OffsetDateTime,
+        gap_after: time::Duration,
+    ) -> Result<Option<CollectionGap>, SetStatusError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let last_success = sqlx::query_scalar::<_, i64>(
+            "SELECT last_success FROM collector_polls WHERE target = $1",
+        )
+        .bind(target)
+        .fetch_optional(&mut *txn)
+        .await?;
+        let gap = last_success
+            .and_then(|last_success| OffsetDateTime::from_unix_timestamp(last_success).ok())
+            .filter(|last_success| time - *last_success > gap_after)
+            .map(|last_success| CollectionGap {
+                start_time: last_success,
+                end_time: Some(time),
+            });
+        if let Some(gap) = &gap {
+            sqlx::query(
+                "INSERT INTO collector_gaps (target, start_time, end_time) VALUES ($1, $2, $3)",
+            )
+            .bind(target)
+            .bind(gap.start_time.unix_timestamp())
+            .bind(time.unix_timestamp())
+            .execute(&mut *txn)
+            .await?;
+        }
+        sqlx::query(
+            "INSERT INTO collector_polls (target, last_success) VALUES ($1, $2)
+            ON CONFLICT (target) DO UPDATE SET last_success = excluded.last_success",
+        )
+        .bind(target)
+        .bind(time.unix_timestamp())
+        .execute(&mut *txn)
+        .await?;
+        txn.commit().await?;
+        Ok(gap)
+    }
+
+    async fn get_last_poll(
+        &mut self,
+        target: &str,
+    ) -> Result<Option<OffsetDateTime>, GetStatusError> {
+        let last_success = sqlx::query_scalar::<_, i64>(
+            "SELECT last_success FROM collector_polls WHERE target = $1",
+        )
+        .bind(target)
+        .fetch_optional(&mut *self.connection)
+        .await?;
+        last_success
+            .map(|last_success| parsed::to_date_time(target, "last poll", last_success))
+            .transpose()
+    }
+
+    async fn get_collection_gaps(
+        &mut self,
+        target: &str,
+        start_time: OffsetDateTime,
+        end_time: OffsetDateTime,
+    ) -> Result<Vec<CollectionGap>, GetStatusError> {
+        sqlx::query_as::<_, CollectionGapRow>(
+            "SELECT target, start_time, end_time FROM collector_gaps
+            WHERE target = $1 AND start_time <= $2 AND end_time >= $3
+            ORDER BY start_time",
+        )
+        .bind(target)
+        .bind(end_time.unix_timestamp())
+        .bind(start_time.unix_timestamp())
+        .fetch_all(&mut *self.connection)
+        .await?
+        .into_iter()
+        .map(CollectionGap::try_from)
+        .collect()
+    }
+
+    async fn purge_history(&mut self, before: OffsetDateTime) -> Result<u64, MaintenanceError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let lines =
+            sqlx::query("DELETE FROM line_history WHERE end_time IS NOT NULL AND end_time < $1")
+                .bind(before.unix_timestamp())
+                .execute(&mut *txn)
+                .await?;
+        let stations =
+            sqlx::query("DELETE FROM station_history WHERE end_time IS NOT NULL AND end_time < $1")
+                .bind(before.unix_timestamp())
+                .execute(&mut *txn)
+                .await?;
+        sqlx::query("DELETE FROM collector_gaps WHERE end_time < $1")
+            .bind(before.unix_timestamp())
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query(
+            "DELETE FROM line_history_status WHERE NOT EXISTS (
+                SELECT 1 FROM line_history h
+                WHERE h.line = line_history_status.line
+                    AND h.start_time = line_history_status.start_time
+            )",
+        )
+        .execute(&mut *txn)
+        .await?;
+        sqlx::query(
+            "DELETE FROM station_history_status WHERE NOT EXISTS (
+                SELECT 1 FROM station_history h
+                WHERE h.station_id = station_history_status.station_id
+                    AND h.start_time = station_history_status.start_time
+            )",
+        )
+        .execute(&mut *txn)
+        .await?;
+        txn.commit().await?;
+        Ok(DB::rows_affected(&lines) + DB::rows_affected(&stations))
+    }
+
+    async fn merge_line_history(
+        &mut self,
+        same_status: &SameLineStatus,
+    ) -> Result<u64, MaintenanceError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let rows = sqlx::query_as::<_, HistoryRow>(
+            "SELECT line AS key, start_time, end_time, data FROM line_history ORDER BY line, start_time",
+        )
+        .fetch_all(&mut *txn)
+        .await?;
+        let runs = compaction::find_merge_runs(&rows, |line, old, new| {
+            match (
+                serde_json::from_slice::<Value>(old),
+                serde_json::from_slice::<Value>(new),
+            ) {
+                (Ok(old), Ok(new)) => same_status(line, &old, &new),
+                _ => false,
+            }
+        });
+        let mut removed = 0;
+        for run in runs {
+            for start_time in &run.removed_start_times {
+                sqlx::query("DELETE FROM line_history WHERE line = $1 AND start_time = $2")
+                    .bind(&run.key)
+                    .bind(start_time)
+                    .execute(&mut *txn)
+                    .await?;
+                sqlx::query("DELETE FROM line_history_status WHERE line = $1 AND start_time = $2")
+                    .bind(&run.key)
+                    .bind(start_time)
+                    .execute(&mut *txn)
+                    .await?;
+                removed += 1;
+            }
+            sqlx::query(
+                "UPDATE line_history SET start_time = $1 WHERE line = $2 AND start_time = $3",
+            )
+            .bind(run.start_time)
+            .bind(&run.key)
+            .bind(run.kept_start_time)
+            .execute(&mut *txn)
+            .await?;
+            sqlx::query(
+                "UPDATE line_history_status SET start_time = $1 WHERE line = $2 AND start_time = $3",
+            )
+            .bind(run.start_time)
+            .bind(&run.key)
+            .bind(run.kept_start_time)
+            .execute(&mut *txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(removed)
+    }
+
+    async fn merge_station_history(
+        &mut self,
+        same_status: &SameStationStatus,
+    ) -> Result<u64, MaintenanceError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let rows = sqlx::query_as::<_, HistoryRow>(
+            "SELECT station_id AS key, start_time, end_time, data FROM station_history ORDER BY station_id, start_time",
+        )
+        .fetch_all(&mut *txn)
+        .await?;
+        let runs = compaction::find_merge_runs(&rows, |station, old, new| {
+            match (
+                serde_json::from_slice::<Vec<Value>>(old),
+                serde_json::from_slice::<Vec<Value>>(new),
+            ) {
+                (Ok(old), Ok(new)) => same_status(station, &old, &new),
+                _ => false,
+            }
+        });
+        let mut removed = 0;
+        for run in runs {
+            for start_time in &run.removed_start_times {
+                sqlx::query(
+                    "DELETE FROM station_history WHERE station_id = $1 AND start_time = $2",
+                )
+                .bind(&run.key)
+                .bind(start_time)
+                .execute(&mut *txn)
+                .await?;
+                sqlx::query(
+                    "DELETE FROM station_history_status WHERE station_id = $1 AND start_time = $2",
+                )
+                .bind(&run.key)
+                .bind(start_time)
+                .execute(&mut *txn)
+                .await?;
+                removed += 1;
+            }
+            sqlx::query(
+                "UPDATE station_history SET start_time = $1 WHERE station_id = $2 AND start_time = $3",
+            )
+            .bind(run.start_time)
+            .bind(&run.key)
+            .bind(run.kept_start_time)
+            .execute(&mut *txn)
+            .await?;
+            sqlx::query(
+                "UPDATE station_history_status SET start_time = $1 WHERE station_id = $2 AND start_time = $3",
+            )
+            .bind(run.start_time)
+            .bind(&run.key)
+            .bind(run.kept_start_time)
+            .execute(&mut *txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(removed)
+    }
+
+    async fn strip_line_history(
+        &mut self,
+        strip: &StripLineStatus,
+    ) -> Result<u64, MaintenanceError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let rows = sqlx::query_as::<_, HistoryRow>(
+            "SELECT line AS key, start_time, end_time, data FROM line_history WHERE end_time IS NOT NULL",
+        )
+        .fetch_all(&mut *txn)
+        .await?;
+        let mut changed = 0;
+        for row in rows {
+            let data = serde_json::from_slice::<Value>(&row.data)?;
+            let stripped = strip(&data);
+            if stripped == data {
+                continue;
+            }
+            sqlx::query("UPDATE line_history SET data = $1 WHERE line = $2 AND start_time = $3")
+                .bind(serde_json::to_vec(&stripped)?)
+                .bind(&row.key)
+                .bind(row.start_time)
+                .execute(&mut *txn)
+                .await?;
+            changed += 1;
+        }
+        txn.commit().await?;
+        Ok(changed)
+    }
+
+    async fn strip_station_history(
+        &mut self,
+        strip: &StripStationStatus,
+    ) -> Result<u64, MaintenanceError> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let rows = sqlx::query_as::<_, HistoryRow>(
+            "SELECT station_id AS key, start_time, end_time, data FROM station_history WHERE end_time IS NOT NULL",
+        )
+        .fetch_all(&mut *txn)
+        .await?;
+        let mut changed = 0;
+        for row in rows {
+            let data = serde_json::from_slice::<Vec<Value>>(&row.data)?;
+            let stripped = strip(&data);
+            if stripped == data {
+                continue;
+            }
+            sqlx::query(
+                "UPDATE station_history SET data = $1 WHERE station_id = $2 AND start_time = $3",
+            )
+            .bind(serde_json::to_vec(&stripped)?)
+            .bind(&row.key)
+            .bind(row.start_time)
+            .execute(&mut *txn)
+            .await?;
+            changed += 1;
+        }
+        txn.commit().await?;
+        Ok(changed)
+    }
+
+    async fn vacuum(&mut self) -> Result<(), MaintenanceError> {
+        sqlx::query("VACUUM").execute(&mut *self.connection).await?;
+        Ok(())
+    }
+
+    async fn get_webhooks(&mut self) -> Result<Vec<Webhook>, WebhookError> {
+        sqlx::query_as::<_, WebhookRow>(
+            "SELECT id, url, secret, lines, modes, stations, min_line_severity, min_station_severity
+            FROM webhooks ORDER BY id",
+        )
+        .fetch_all(&mut *self.connection)
+        .await?
+        .into_iter()
+        .map(Webhook::try_from)
+        .collect()
+    }
+
+    async fn add_webhook(&mut self, target: &WebhookTarget) -> Result<Webhook, WebhookError> {
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO webhooks
+            (url, secret, lines, modes, stations, min_line_severity, min_station_severity, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id",
+        )
+        .bind(&target.url)
+        .bind(&target.secret)
+        .bind(serde_json::to_string(&target.lines)?)
+        .bind(serde_json::to_string(&target.modes)?)
+        .bind(serde_json::to_string(&target.stations)?)
+        .bind(target.min_line_severity.map(parsed::state_name))
+        .bind(target.min_station_severity.map(parsed::state_name))
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_one(&mut *self.connection)
+        .await?;
+        Ok(Webhook {
+            id,
+            target: target.clone(),
+        })
+    }
+
+    async fn delete_webhook(&mut self, id: i64) -> Result<bool, WebhookError> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1")
+            .bind(id)
+            .execute(&mut *self.connection)
+            .await?;
+        Ok(DB::rows_affected(&result) > 0)
+    }
+
+    async fn add_webhook_dead_letter(
+        &mut self,
+        dead_letter: &WebhookDeadLetter,
+    ) -> Result<(), WebhookError> {
+        sqlx::query(
+            "INSERT INTO webhook_dead_letters (webhook_id, url, payload, error, attempts, failed_at)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(dead_letter.webhook_id)
+        .bind(&dead_letter.url)
+        .bind(&dead_letter.payload)
+        .bind(&dead_letter.error)
+        .bind(dead_letter.attempts)
+        .bind(dead_letter.failed_at.unix_timestamp())
+        .execute(&mut *self.connection)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_webhook_dead_letters(
+        &mut self,
+        limit: i64,
+    ) -> Result<Vec<WebhookDeadLetter>, WebhookError> {
+        sqlx::query_as::<_, WebhookDeadLetterRow>(
+            "SELECT webhook_id, url, payload, error, attempts, failed_at
+            FROM webhook_dead_letters ORDER BY failed_at DESC, id DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&mut *self.connection)
+        .await?
+        .into_iter()
+        .map(WebhookDeadLetter::try_from)
+        .collect()
+    }
+
+    async fn get_subscriptions(&mut self) -> Result<Vec<StoredSubscription>, SubscriptionError> {
+        sqlx::query_as::<_, SubscriptionRow>(
+            "SELECT id, name, lines, min_severity, active_from, active_until, days, target
+            FROM subscriptions ORDER BY id",
+        )
+        .fetch_all(&mut *self.connection)
+        .await?
+        .into_iter()
+        .map(StoredSubscription::try_from)
+        .collect()
+    }
+
+    async fn get_subscription(
+        &mut self,
+        id: i64,
+    ) -> Result<Option<StoredSubscription>, SubscriptionError> {
+        sqlx::query_as::<_, SubscriptionRow>(
+            "SELECT id, name, lines, min_severity, active_from, active_until, days, target
+            FROM subscriptions WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&mut *self.connection)
+        .await?
+        .map(StoredSubscription::try_from)
+        .transpose()
+    }
+
+    async fn add_subscription(
+        &mut self,
+        subscription: &Subscription,
+    ) -> Result<StoredSubscription, SubscriptionError> {
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO subscriptions
+            (name, lines, min_severity, active_from, active_until, days, target, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id",
+        )
+        .bind(&subscription.name)
+        .bind(serde_json::to_string(&subscription.lines)?)
+        .bind(parsed::state_name(subscription.min_severity))
+        .bind(subscription.active_from.map(subscriptions::to_minutes))
+        .bind(subscription.active_until.map(subscriptions::to_minutes))
+        .bind(serde_json::to_string(&subscriptions::to_day_numbers(
+            &subscription.days,
+        ))?)
+        .bind(serde_json::to_string(&subscription.target)?)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_one(&mut *self.connection)
+        .await?;
+        Ok(StoredSubscription {
+            id,
+            subscription: subscription.clone(),
+        })
+    }
+
+    async fn update_subscription(
+        &mut self,
+        id: i64,
+        subscription: &Subscription,
+    ) -> Result<bool, SubscriptionError> {
+        let result = sqlx::query(
+            "UPDATE subscriptions
+            SET name = $1, lines = $2, min_severity = $3, active_from = $4, active_until = $5, days = $6,
+                target = $7
+            WHERE id = $8",
+        )
+        .bind(&subscription.name)
+        .bind(serde_json::to_string(&subscription.lines)?)
+        .bind(parsed::state_name(subscription.min_severity))
+        .bind(subscription.active_from.map(subscriptions::to_minutes))
+        .bind(subscription.active_until.map(subscriptions::to_minutes))
+        .bind(serde_json::to_string(&subscriptions::to_day_numbers(
+            &subscription.days,
+        ))?)
+        .bind(serde_json::to_string(&subscription.target)?)
+        .bind(id)
+        .execute(&mut *self.connection)
+        .await?;
+        Ok(DB::rows_affected(&result) > 0)
+    }
+
+    async fn delete_subscription(&mut self, id: i64) -> Result<bool, SubscriptionError> {
+        let result = sqlx::query("DELETE FROM subscriptions WHERE id = $1")
+            .bind(id)
+            .execute(&mut *self.connection)
+            .await?;
+        Ok(DB::rows_affected(&result) > 0)
+    }
+
+    async fn get_or_insert_vapid_key(&mut self, private_key: &[u8]) -> Result<Vec<u8>, PushError> {
+        sqlx::query(
+            "INSERT INTO vapid_keys (id, private_key, created_at) VALUES (1, $1, $2)
+            ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(private_key)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .execute(&mut *self.connection)
+        .await?;
+        let stored =
+            sqlx::query_scalar::<_, Vec<u8>>("SELECT private_key FROM vapid_keys WHERE id = 1")
+                .fetch_one(&mut *self.connection)
+                .await?;
+        Ok(stored)
+    }
+
+    async fn get_push_subscriptions(&mut self) -> Result<Vec<PushSubscription>, PushError> {
+        sqlx::query_as::<_, PushSubscriptionRow>(
+            "SELECT endpoint, p256dh, auth, lines, stations, min_line_severity
+            FROM push_subscriptions ORDER BY created_at",
+        )
+        .fetch_all(&mut *self.connection)
+        .await?
+        .into_iter()
+        .map(PushSubscription::try_from)
+        .collect()
+    }
+
+    async fn set_push_subscription(
+        &mut self,
+        subscription: &PushSubscription,
+    ) -> Result<(), PushError> {
+        sqlx::query(
+            "INSERT INTO push_subscriptions
+            (endpoint, p256dh, auth, lines, stations, min_line_severity, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (endpoint) DO UPDATE SET
+            p256dh = excluded.p256dh, auth = excluded.auth, lines = excluded.lines,
+            stations = excluded.stations, min_line_severity = excluded.min_line_severity",
+        )
+        .bind(&subscription.endpoint)
+        .bind(&subscription.p256dh)
+        .bind(&subscription.auth)
+        .bind(serde_json::to_string(&subscription.lines)?)
+        .bind(serde_json::to_string(&subscription.stations)?)
+        .bind(subscription.min_line_severity.map(parsed::state_name))
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .execute(&mut *self.connection)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_push_subscription(&mut self, endpoint: &str) -> Result<bool, PushError> {
+        let result = sqlx::query("DELETE FROM push_subscriptions WHERE endpoint = $1")
+            .bind(endpoint)
+            .execute(&mut *self.connection)
+            .await?;
+        Ok(DB::rows_affected(&result) > 0)
+    }
+}
+
+impl<DB> SqlConnection<DB>
+where
+    DB: SqlDatabase,
+    DB::Arguments: IntoArguments<DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> i64: Encode<'q, DB> + Type<DB>,
+    for<'q> bool: Encode<'q, DB> + Type<DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<&'q str>: Encode<'q, DB> + Type<DB>,
+    for<'q> String: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<String>: Encode<'q, DB> + Type<DB>,
+    for<'r> HistoryRow: FromRow<'r, DB::Row>,
+{
+    pub fn new(connection: PoolConnection<DB>) -> Self {
+        SqlConnection { connection }
+    }
+
+    /// Adds a condition that `column` is one of `values`, unless `values` is empty.
+    fn push_in_filter(query: &mut QueryBuilder<DB>, column: &str, values: &[String]) {
+        if values.is_empty() {
+            return;
+        }
+        query.push(format!(" AND {} IN (", column));
+        let mut list = query.separated(", ");
+        for value in values {
+            list.push_bind(value.clone());
+        }
+        list.push_unseparated(")");
+    }
+
+    /// Adds a condition that the history row `h` covers `time`, or is still open if `time` is
+    /// `None`.
+    fn push_point_in_time(query: &mut QueryBuilder<DB>, time: Option<OffsetDateTime>) {
+        match time {
+            None => {
+                query.push("h.end_time IS NULL");
+            }
+            Some(time) => {
+                query.push("h.start_time <= ");
+                query.push_bind(time.unix_timestamp());
+                query.push(" AND (h.end_time IS NULL OR h.end_time > ");
+                query.push_bind(time.unix_timestamp());
+                query.push(")");
+            }
+        }
+    }
+
+    /// Parses any rows that haven't been parsed by the current version of the parser, so that
+    /// older rows can be queried in the same way as new ones.
+    pub async fn backfill_parsed_status(&mut self) -> Result<(), sqlx::Error> {
+        let mut txn = self.connection.begin().await?;
+        DB::lock_for_update(&mut txn).await?;
+        let line_rows = sqlx::query_as::<_, HistoryRow>(
+            "SELECT line AS key, start_time, end_time, data FROM line_history
+            WHERE parser_version IS NULL OR parser_version < $1",
+        )
+        .bind(tfl::PARSER_VERSION)
+        .fetch_all(&mut *txn)
+        .await?;
+        for row in &line_rows {
+            let data = serde_json::from_slice::<Value>(&row.data).unwrap_or(Value::Null);
+            Self::write_parsed_line_status(&mut txn, &row.key, row.start_time, &data).await?;
+        }
+        let station_rows = sqlx::query_as::<_, HistoryRow>(
+            "SELECT station_id AS key, start_time, end_time, data FROM station_history
+            WHERE parser_version IS NULL OR parser_version < $1",
+        )
+        .bind(tfl::PARSER_VERSION)
+        .fetch_all(&mut *txn)
+        .await?;
+        for row in &station_rows {
+            let data = serde_json::from_slice::<Vec<Value>>(&row.data).unwrap_or_default();
+            Self::write_parsed_station_status(&mut txn, &row.key, row.start_time, &data).await?;
+        }
+        txn.commit().await?;
+        if !line_rows.is_empty() || !station_rows.is_empty() {
+            log::info!(
+                "Parsed {} line and {} station history rows",
+                line_rows.len(),
+                station_rows.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Stores the parsed form of a line history row alongside the raw data, returning it.
+    async fn write_parsed_line_status(
+        connection: &mut DB::Connection,
+        line: &str,
+        start_time: i64,
+        data: &Value,
+    ) -> Result<Option<ParsedLine>, sqlx::Error> {
+        let parsed = parsed::parse_line(line, data);
+        sqlx::query("DELETE FROM line_history_status WHERE line = $1 AND start_time = $2")
+            .bind(line)
+            .bind(start_time)
+            .execute(&mut *connection)
+            .await?;
+        for (position, status) in parsed.iter().flat_map(|p| &p.statuses).enumerate() {
+            sqlx::query(
+                "INSERT INTO line_history_status
+                    (line, start_time, position, status, reason, validity_periods, disruption)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(line)
+            .bind(start_time)
+            .bind(position as i64)
+            .bind(parsed::state_name(status.status))
+            .bind(status.reason.as_deref())
+            .bind(parsed::to_json(&status.validity_periods))
+            .bind(status.disruption.as_ref().map(parsed::to_json))
+            .execute(&mut *connection)
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE line_history SET mode = $1, parsed = $2, parser_version = $3
+            WHERE line = $4 AND start_time = $5",
+        )
+        .bind(parsed.as_ref().map(|p| p.mode.as_str()))
+        .bind(parsed.is_some())
+        .bind(tfl::PARSER_VERSION)
+        .bind(line)
+        .bind(start_time)
+        .execute(&mut *connection)
+        .await?;
+        Ok(parsed)
+    }
+
+    /// Stores the parsed form of a station history row alongside the raw data, returning it.
+    async fn write_parsed_station_status(
+        connection: &mut DB::Connection,
+        station: &str,
+        start_time: i64,
+        data: &[Value],
+    ) -> Result<Option<Vec<StationStatus>>, sqlx::Error> {
+        let parsed = parsed::parse_station(station, data);
+        sqlx::query("DELETE FROM station_history_status WHERE station_id = $1 AND start_time = $2")
+            .bind(station)
+            .bind(start_time)
+            .execute(&mut *connection)
+            .await?;
+        for (position, status) in parsed.iter().flatten().enumerate() {
+            sqlx::query(
+                "INSERT INTO station_history_status (station_id, start_time, position, status, description)
+                VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(station)
+            .bind(start_time)
+            .bind(position as i64)
+            .bind(parsed::state_name(status.status))
+            .bind(&status.description)
+            .execute(&mut *connection)
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE station_history SET parsed = $1, parser_version = $2
+            WHERE station_id = $3 AND start_time = $4",
+        )
+        .bind(parsed.is_some())
+        .bind(tfl::PARSER_VERSION)
+        .bind(station)
+        .bind(start_time)
+        .execute(&mut *connection)
+        .await?;
+        Ok(parsed)
+    }
+}
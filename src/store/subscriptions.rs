@@ -0,0 +1,79 @@
+use serde_json::Value;
+use time::{Time, Weekday};
+
+use super::{StoredSubscription, SubscriptionError};
+use crate::types::{LineState, Subscription};
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SubscriptionRow {
+    pub id: i64,
+    pub name: String,
+    pub lines: String,
+    pub min_severity: String,
+    pub active_from: Option<i64>,
+    pub active_until: Option<i64>,
+    pub days: String,
+    pub target: String,
+}
+
+impl TryFrom<SubscriptionRow> for StoredSubscription {
+    type Error = SubscriptionError;
+
+    fn try_from(row: SubscriptionRow) -> Result<Self, Self::Error> {
+        let min_severity: LineState = serde_json::from_value(Value::String(row.min_severity))?;
+        let days = serde_json::from_str::<Vec<u8>>(&row.days)?
+            .into_iter()
+            .map(|day| to_weekday(row.id, day))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(StoredSubscription {
+            id: row.id,
+            subscription: Subscription {
+                name: row.name,
+                lines: serde_json::from_str(&row.lines)?,
+                min_severity,
+                active_from: row
+                    .active_from
+                    .map(|minutes| to_time(row.id, minutes))
+                    .transpose()?,
+                active_until: row
+                    .active_until
+                    .map(|minutes| to_time(row.id, minutes))
+                    .transpose()?,
+                days,
+                target: serde_json::from_str(&row.target)?,
+            },
+        })
+    }
+}
+
+/// Times of day are stored as the number of minutes after midnight.
+pub fn to_minutes(time: Time) -> i64 {
+    i64::from(time.hour()) * 60 + i64::from(time.minute())
+}
+
+fn to_time(id: i64, minutes: i64) -> Result<Time, SubscriptionError> {
+    u8::try_from(minutes / 60)
+        .ok()
+        .and_then(|hours| Time::from_hms(hours, (minutes % 60) as u8, 0).ok())
+        .ok_or_else(|| {
+            SubscriptionError::InvalidData(format!(
+                "Subscription {}: Invalid time of day: {}",
+                id, minutes
+            ))
+        })
+}
+
+/// Weekdays are stored as their number, starting from 1 for Monday.
+pub fn to_day_numbers(days: &[Weekday]) -> Vec<u8> {
+    days.iter().map(|day| day.number_from_monday()).collect()
+}
+
+fn to_weekday(id: i64, number: u8) -> Result<Weekday, SubscriptionError> {
+    if !(1..=7).contains(&number) {
+        return Err(SubscriptionError::InvalidData(format!(
+            "Subscription {}: Invalid weekday: {}",
+            id, number
+        )));
+    }
+    Ok(Weekday::Monday.nth_next(number - 1))
+}
@@ -0,0 +1,68 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use time::OffsetDateTime;
+
+use super::{Webhook, WebhookDeadLetter, WebhookError};
+use crate::config::WebhookTarget;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct WebhookRow {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    pub lines: String,
+    pub modes: String,
+    pub stations: String,
+    pub min_line_severity: Option<String>,
+    pub min_station_severity: Option<String>,
+}
+
+impl TryFrom<WebhookRow> for Webhook {
+    type Error = WebhookError;
+
+    fn try_from(row: WebhookRow) -> Result<Self, Self::Error> {
+        Ok(Webhook {
+            id: row.id,
+            target: WebhookTarget {
+                url: row.url,
+                secret: row.secret,
+                lines: serde_json::from_str(&row.lines)?,
+                modes: serde_json::from_str(&row.modes)?,
+                stations: serde_json::from_str(&row.stations)?,
+                min_line_severity: row.min_line_severity.map(parse_severity).transpose()?,
+                min_station_severity: row.min_station_severity.map(parse_severity).transpose()?,
+            },
+        })
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct WebhookDeadLetterRow {
+    pub webhook_id: Option<i64>,
+    pub url: String,
+    pub payload: String,
+    pub error: String,
+    pub attempts: i64,
+    pub failed_at: i64,
+}
+
+impl TryFrom<WebhookDeadLetterRow> for WebhookDeadLetter {
+    type Error = WebhookError;
+
+    fn try_from(row: WebhookDeadLetterRow) -> Result<Self, Self::Error> {
+        Ok(WebhookDeadLetter {
+            webhook_id: row.webhook_id,
+            url: row.url,
+            payload: row.payload,
+            error: row.error,
+            attempts: row.attempts,
+            failed_at: OffsetDateTime::from_unix_timestamp(row.failed_at).map_err(|_| {
+                WebhookError::InvalidData(format!("Invalid failure time: {}", row.failed_at))
+            })?,
+        })
+    }
+}
+
+fn parse_severity<S: DeserializeOwned>(name: String) -> Result<S, serde_json::Error> {
+    serde_json::from_value(Value::String(name))
+}
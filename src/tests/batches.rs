@@ -0,0 +1,50 @@
+use serde_json::{json, Value};
+
+use super::{fixture, TestApp};
+use crate::tfl::PollTarget;
+
+const BUS_LINES_PATH: &str = "/Line/Mode/bus";
+const TUBE_STATUS_PATH: &str = "/Line/Mode/tube/Status";
+const SERVICE_UNAVAILABLE: &str = "503 Service Unavailable";
+
+fn bus_lines(ids: &[String]) -> String {
+    let lines = ids
+        .iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "modeName": "bus",
+                "lineStatuses": [{ "statusSeverity": 10, "validityPeriods": [] }],
+            })
+        })
+        .collect::<Vec<_>>();
+    Value::from(lines).to_string()
+}
+
+#[rocket::async_test]
+async fn keeps_the_batches_which_load() {
+    let app = TestApp::start_with(|config| {
+        config.modes = vec!["tube".to_string(), "bus".to_string()];
+    })
+    .await;
+    let ids = (1..=60).map(|id| id.to_string()).collect::<Vec<_>>();
+    let first_batch = format!("/Line/{}/Status", ids[..50].join(","));
+    let second_batch = format!("/Line/{}/Status", ids[50..].join(","));
+    app.tfl.serve(BUS_LINES_PATH, bus_lines(&ids));
+    app.tfl.serve(&first_batch, bus_lines(&ids[..50]));
+    app.tfl.fail(&second_batch, SERVICE_UNAVAILABLE, &[]);
+    app.tfl
+        .serve(TUBE_STATUS_PATH, fixture("line_status_recovered.json"));
+    assert!(app.try_poll_target(PollTarget::Lines).await);
+
+    let history = app.line_history().await;
+    assert_eq!(history["central"]["metadata"]["mode"], "tube");
+    assert_eq!(history["50"]["metadata"]["mode"], "bus");
+    assert!(history.get("51").is_none());
+
+    // The bus lines are only listed again once they're a day old
+    app.tfl.serve(&second_batch, bus_lines(&ids[50..]));
+    assert!(app.try_poll_target(PollTarget::Lines).await);
+    assert_eq!(app.line_history().await["60"]["metadata"]["mode"], "bus");
+    assert_eq!(app.tfl.requests(BUS_LINES_PATH), 1);
+}
@@ -0,0 +1,54 @@
+use super::{fixture, TestApp, LINE_STATUS_PATH};
+
+#[rocket::async_test]
+async fn has_an_entry_for_each_disruption() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+
+    let feed = app.get_text("/feeds/lines.atom").await;
+    assert_eq!(feed.matches("<entry>").count(), 1);
+    assert!(feed.contains("<title>central: Part Closure (ongoing)</title>"));
+    let feed = app.get_text("/feeds/lines/bakerloo.atom").await;
+    assert_eq!(feed.matches("<entry>").count(), 0);
+}
+
+#[rocket::async_test]
+async fn has_no_entries_for_lines_closing_for_the_night() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+    app.tfl.serve(
+        LINE_STATUS_PATH,
+        fixture("line_status_closed_for_the_night.json"),
+    );
+    app.poll().await;
+
+    let history = app.line_history().await;
+    assert_eq!(
+        history["central"]["history"][1]["entries"][0]["status"],
+        "ServiceClosed"
+    );
+    let feed = app.get_text("/feeds/lines.atom").await;
+    assert_eq!(feed.matches("<entry>").count(), 0);
+    let feed = app.get_text("/feeds/lines/central.atom").await;
+    assert_eq!(feed.matches("<entry>").count(), 0);
+}
+
+#[rocket::async_test]
+async fn has_a_calendar_of_planned_closures() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+
+    let calendar = app.get_text("/feeds/lines/central.ics").await;
+    assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 1);
+    assert!(calendar.contains("DTSTART:20261024T043000Z\r\n"));
+    assert!(calendar.contains("DTEND:20261026T012900Z\r\n"));
+    assert!(calendar.contains("SUMMARY:central: Part Closure\r\n"));
+    let calendar = app.get_text("/feeds/lines/bakerloo.ics").await;
+    assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 0);
+}
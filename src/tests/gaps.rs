@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use rocket::tokio::time::sleep;
+
+use super::{fixture, TestApp, LINE_STATUS_PATH};
+
+#[rocket::async_test]
+async fn records_gaps_between_polls_which_are_too_far_apart() {
+    let app = TestApp::start_with(|config| config.gap_after_secs = 1).await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+    let gaps = app.get_history("/api/v1/history/gaps").await;
+    assert_eq!(gaps["lines"].as_array().unwrap().len(), 0);
+    assert_eq!(gaps["stations"].as_array().unwrap().len(), 0);
+
+    sleep(Duration::from_secs(2)).await;
+    app.poll().await;
+    let gaps = app.get_history("/api/v1/history/gaps").await;
+    let lines = gaps["lines"].as_array().unwrap();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0]["to"].is_string());
+    assert_eq!(gaps["stations"].as_array().unwrap().len(), 1);
+    // It starts from the last poll before it
+    let history = app.line_history().await;
+    assert_eq!(history["central"]["history"][0]["from"], lines[0]["from"]);
+
+    // Once polling stops for long enough, the gap since the last poll is reported as ongoing
+    sleep(Duration::from_secs(2)).await;
+    let gaps = app.get_history("/api/v1/history/gaps").await;
+    let lines = gaps["lines"].as_array().unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["from"], lines[0]["to"]);
+    assert!(lines[1]["to"].is_null());
+}
@@ -0,0 +1,116 @@
+use time::{format_description, OffsetDateTime};
+
+use super::{fixture, TestApp, LINE_STATUS_PATH};
+use crate::config::StoreConfig;
+use crate::store::Store;
+use crate::tfl::PollTarget;
+
+const SERVICE_UNAVAILABLE: &str = "503 Service Unavailable";
+const TOO_MANY_REQUESTS: &str = "429 Too Many Requests";
+
+#[rocket::async_test]
+async fn retries_server_errors_then_pauses_polling() {
+    let app = TestApp::start_with(|config| config.breaker_threshold = 2).await;
+    app.tfl.fail(LINE_STATUS_PATH, SERVICE_UNAVAILABLE, &[]);
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "closed");
+    assert!(health["lastSuccess"].is_null());
+
+    assert!(!app.try_poll().await);
+    assert_eq!(app.tfl.requests(LINE_STATUS_PATH), 3);
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "closed");
+    assert_eq!(health["consecutiveFailures"], 1);
+    assert!(health["lastError"].as_str().unwrap().contains("503"));
+
+    assert!(!app.try_poll().await);
+    assert_eq!(app.tfl.requests(LINE_STATUS_PATH), 6);
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "open");
+    assert!(health["pausedUntil"].is_string());
+
+    // TfL isn't asked again until the cooldown is over
+    assert!(!app.try_poll().await);
+    assert_eq!(app.tfl.requests(LINE_STATUS_PATH), 6);
+}
+
+#[rocket::async_test]
+async fn waits_as_long_as_tfl_asks_when_rate_limited() {
+    let app = TestApp::start().await;
+    app.tfl
+        .fail(LINE_STATUS_PATH, TOO_MANY_REQUESTS, &[("Retry-After", "0")]);
+    assert!(!app.try_poll().await);
+    assert_eq!(app.tfl.requests(LINE_STATUS_PATH), 3);
+    assert_eq!(app.get_json("/api/v1/health").await["breaker"], "closed");
+
+    // Waits longer than the maximum backoff pause polling rather than being retried
+    app.tfl.fail(
+        LINE_STATUS_PATH,
+        TOO_MANY_REQUESTS,
+        &[("Retry-After", "120")],
+    );
+    assert!(!app.try_poll().await);
+    assert_eq!(app.tfl.requests(LINE_STATUS_PATH), 4);
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "open");
+    let paused_until = OffsetDateTime::parse(
+        health["pausedUntil"].as_str().unwrap(),
+        &format_description::well_known::Rfc3339,
+    )
+    .unwrap();
+    let pause = paused_until - OffsetDateTime::now_utc();
+    assert!(pause.whole_seconds() > 110 && pause.whole_seconds() <= 120);
+}
+
+#[rocket::async_test]
+async fn resumes_polling_once_tfl_recovers() {
+    let app = TestApp::start_with(|config| {
+        config.breaker_threshold = 1;
+        config.breaker_cooldown_secs = 0;
+    })
+    .await;
+    app.tfl.fail(LINE_STATUS_PATH, SERVICE_UNAVAILABLE, &[]);
+    assert!(!app.try_poll().await);
+    assert_eq!(app.get_json("/api/v1/health").await["breaker"], "open");
+
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "closed");
+    assert_eq!(health["consecutiveFailures"], 0);
+    assert!(health["lastSuccess"].is_string());
+    assert!(health["pausedUntil"].is_null());
+}
+
+#[rocket::async_test]
+async fn reports_store_failures_without_touching_the_breaker() {
+    let app = TestApp::start_with(|config| {
+        config.breaker_threshold = 1;
+        config.breaker_cooldown_secs = 0;
+    })
+    .await;
+    app.tfl.fail(LINE_STATUS_PATH, SERVICE_UNAVAILABLE, &[]);
+    assert!(!app.try_poll().await);
+
+    // TfL has recovered, but the status can't be written anywhere
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    let mut closed = Store::new(&StoreConfig::Memory).await.unwrap();
+    closed.shutdown().await;
+    assert!(!app.poller.poll_target(PollTarget::Lines, &mut closed).await);
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "halfOpen");
+    assert_eq!(health["consecutiveFailures"], 1);
+    assert!(health["lastSuccess"].is_null());
+    assert!(health["lastError"].as_str().unwrap().contains("503"));
+    assert!(health["store"]["lastSuccess"].is_null());
+    assert!(health["store"]["lastFailure"].is_string());
+    assert!(health["store"]["lastError"].is_string());
+
+    app.poll().await;
+    let health = app.get_json("/api/v1/health").await;
+    assert_eq!(health["breaker"], "closed");
+    assert_eq!(health["consecutiveFailures"], 0);
+    assert!(health["store"]["lastSuccess"].is_string());
+}
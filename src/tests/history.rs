@@ -0,0 +1,192 @@
+use serde_json::Value;
+
+use super::{fixture, TestApp, DISRUPTION_PATH, LINE_STATUS_PATH};
+
+fn statuses(entry: &Value) -> Vec<&str> {
+    entry["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|status| status["status"].as_str().unwrap())
+        .collect()
+}
+
+fn keys(value: &Value) -> Vec<&str> {
+    let mut keys: Vec<_> = value
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[rocket::async_test]
+async fn records_lines_with_several_statuses() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+
+    let history = app.line_history().await;
+    assert_eq!(keys(&history), ["bakerloo", "central", "dlr"]);
+    assert_eq!(history["dlr"]["metadata"]["mode"], "dlr");
+    assert_eq!(
+        statuses(&history["bakerloo"]["history"][0]),
+        ["GoodService"]
+    );
+
+    let central = history["central"]["history"].as_array().unwrap();
+    assert_eq!(central.len(), 1);
+    assert!(central[0]["to"].is_null());
+    assert_eq!(statuses(&central[0]), ["PartClosure", "SevereDelays"]);
+    let closure = &central[0]["entries"][0];
+    assert_eq!(
+        closure["validityPeriods"][0]["from"],
+        "2026-10-24T04:30:00Z"
+    );
+    assert_eq!(closure["validityPeriods"][0]["isNow"], false);
+    assert_eq!(closure["disruption"]["category"], "PlannedWork");
+    assert!(central[0]["entries"][1]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("signal failure"));
+}
+
+#[rocket::async_test]
+async fn closes_a_period_when_the_line_recovers() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+
+    let history = app.line_history().await;
+    let central = history["central"]["history"].as_array().unwrap();
+    assert_eq!(central.len(), 2);
+    assert_eq!(statuses(&central[0]), ["PartClosure", "SevereDelays"]);
+    assert_eq!(central[0]["to"], central[1]["from"]);
+    assert_eq!(statuses(&central[1]), ["GoodService"]);
+    assert!(central[1]["to"].is_null());
+    // Lines whose status didn't change carry on in the same period
+    assert_eq!(history["bakerloo"]["history"].as_array().unwrap().len(), 1);
+}
+
+#[rocket::async_test]
+async fn records_station_disruptions_appearing_and_disappearing() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+    assert_eq!(keys(&app.station_history().await), Vec::<&str>::new());
+
+    app.tfl
+        .serve(DISRUPTION_PATH, fixture("disruptions_stations.json"));
+    app.poll().await;
+    let history = app.station_history().await;
+    assert_eq!(keys(&history), ["940GZZLUBNK", "940GZZLUOXC"]);
+    let bank = &history["940GZZLUBNK"]["history"][0];
+    assert_eq!(statuses(bank), ["PartClosure", "Information"]);
+    assert!(bank["to"].is_null());
+    assert_eq!(
+        statuses(&history["940GZZLUOXC"]["history"][0]),
+        ["InterchangeMessage"]
+    );
+
+    app.tfl.serve(DISRUPTION_PATH, "[]");
+    app.poll().await;
+    let history = app.station_history().await;
+    let bank = history["940GZZLUBNK"]["history"].as_array().unwrap();
+    assert_eq!(bank.len(), 1);
+    assert!(bank[0]["to"].is_string());
+}
+
+#[rocket::async_test]
+async fn leaves_malformed_lines_out_of_the_history() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_malformed.json"));
+    app.poll().await;
+    assert_eq!(keys(&app.line_history().await), ["bakerloo"]);
+
+    // Once TfL sends them properly again they're recorded as normal
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+    let history = app.line_history().await;
+    assert_eq!(keys(&history), ["bakerloo", "central", "dlr"]);
+    assert_eq!(statuses(&history["central"]["history"][0]), ["GoodService"]);
+}
+
+#[rocket::async_test]
+async fn keeps_the_history_when_a_response_is_unreadable() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+    let before = app.line_history().await;
+
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_truncated.json"));
+    assert!(!app.try_poll().await);
+    assert_eq!(app.line_history().await, before);
+    let status = app.get_json("/api/v1/status").await;
+    assert_eq!(
+        statuses(&status["lines"]["central"]),
+        ["PartClosure", "SevereDelays"]
+    );
+}
+
+#[rocket::async_test]
+async fn loads_station_details_from_stop_points() {
+    let app = TestApp::start().await;
+    let details = app.get_json("/api/v1/station-details").await;
+    // Platforms and other child stop points are left out
+    assert_eq!(
+        keys(&details),
+        ["940GZZDLBNK", "940GZZLUBNK", "940GZZLUOXC"]
+    );
+    assert_eq!(details["940GZZLUBNK"]["name"], "Bank Underground Station");
+}
+
+#[rocket::async_test]
+async fn skips_validity_periods_with_bad_dates() {
+    let app = TestApp::start().await;
+    app.tfl.serve(
+        LINE_STATUS_PATH,
+        fixture("line_status_bad_validity_periods.json"),
+    );
+    app.poll().await;
+
+    let history = app.line_history().await;
+    let central = &history["central"]["history"][0];
+    assert_eq!(statuses(central), ["PartClosure", "SevereDelays"]);
+    let closure = central["entries"][0]["validityPeriods"].as_array().unwrap();
+    assert_eq!(closure.len(), 1);
+    assert_eq!(closure[0]["from"], "2026-10-24T04:30:00Z");
+    let delays = &central["entries"][1];
+    assert_eq!(delays["validityPeriods"].as_array().unwrap().len(), 0);
+    assert_eq!(delays["disruption"]["category"], "RealTime");
+}
+
+#[rocket::async_test]
+async fn leaves_lines_closing_for_the_night_out_of_filtered_history() {
+    let app = TestApp::start().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+    app.tfl.serve(
+        LINE_STATUS_PATH,
+        fixture("line_status_closed_for_the_night.json"),
+    );
+    app.poll().await;
+
+    let history = app.filtered_line_history("min_severity=SevereDelays").await;
+    assert_eq!(keys(&history), ["central"]);
+    let central = history["central"]["history"].as_array().unwrap();
+    assert_eq!(central.len(), 1);
+    assert_eq!(statuses(&central[0]), ["PartClosure", "SevereDelays"]);
+}
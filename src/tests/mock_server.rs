@@ -0,0 +1,168 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::spawn;
+
+/// A stand-in for an HTTP server which serves canned responses over HTTP/1.1, so that the poller
+/// can be run against recorded TfL fixtures and webhooks can be delivered somewhere local.
+pub struct MockServer {
+    address: SocketAddr,
+    routes: Arc<Mutex<Routes>>,
+}
+
+#[derive(Default)]
+struct Routes {
+    responses: HashMap<String, String>,
+    /// Responses to send before the one in `responses`, each used once.
+    queued: HashMap<String, VecDeque<String>>,
+    /// The requests each path has had.
+    requests: HashMap<String, Vec<ReceivedRequest>>,
+}
+
+/// A request that the server has received.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub received_at: Instant,
+    /// Header names are lower case.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl MockServer {
+    /// Starts listening on a free local port. Paths without a response get a 404.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let served = routes.clone();
+        spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                spawn(respond(stream, served.clone()));
+            }
+        });
+        MockServer { address, routes }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Serves `body` as JSON for requests to `path`, whatever their query string.
+    pub fn serve(&self, path: &str, body: impl Into<String>) {
+        let body = body.into();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        self.respond_with(path, response);
+    }
+
+    /// Fails requests to `path` with an error status like `503 Service Unavailable`, sending the
+    /// given headers.
+    pub fn fail(&self, path: &str, status: &str, headers: &[(&str, &str)]) {
+        self.respond_with(path, failure(status, headers));
+    }
+
+    /// Fails the next `times` requests to `path` with an error status, before going back to
+    /// whatever it's set to serve.
+    pub fn fail_times(&self, path: &str, times: usize, status: &str) {
+        let mut routes = self.routes.lock().unwrap();
+        let queued = routes.queued.entry(path.to_string()).or_default();
+        queued.extend((0..times).map(|_| failure(status, &[])));
+    }
+
+    /// How many requests have been made to `path`.
+    pub fn requests(&self, path: &str) -> usize {
+        self.received(path).len()
+    }
+
+    /// The requests made to `path`, oldest first.
+    pub fn received(&self, path: &str) -> Vec<ReceivedRequest> {
+        let routes = self.routes.lock().unwrap();
+        routes.requests.get(path).cloned().unwrap_or_default()
+    }
+
+    fn respond_with(&self, path: &str, response: String) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.responses.insert(path.to_string(), response);
+    }
+}
+
+fn failure(status: &str, headers: &[(&str, &str)]) -> String {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect::<String>();
+    format!(
+        "HTTP/1.1 {}\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+        status, headers
+    )
+}
+
+async fn respond(mut stream: TcpStream, routes: Arc<Mutex<Routes>>) {
+    let received_at = Instant::now();
+    // Read up to the end of the headers, then however much body they say there is
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    let header_end = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    };
+    let head = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let path = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|target| target.split('?').next())
+        .unwrap_or_default()
+        .to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+    let content_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or_default();
+    while request.len() < header_end + content_length {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    let body = String::from_utf8_lossy(&request[header_end..]).into_owned();
+
+    let response = {
+        let mut routes = routes.lock().unwrap();
+        routes
+            .requests
+            .entry(path.clone())
+            .or_default()
+            .push(ReceivedRequest {
+                received_at,
+                headers,
+                body,
+            });
+        let queued = routes
+            .queued
+            .get_mut(&path)
+            .and_then(|queued| queued.pop_front());
+        queued.or_else(|| routes.responses.get(&path).cloned())
+    };
+    let response = response.unwrap_or_else(|| {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    });
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,227 @@
+//! End-to-end tests, which poll a stand-in for the TfL API serving recorded fixtures and then
+//! query our API through Rocket's local client.
+
+mod batches;
+mod feeds;
+mod gaps;
+mod health;
+mod history;
+mod mock_server;
+mod webhooks;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::tokio::time::sleep;
+use serde_json::{json, Value};
+use time::ext::NumericalDuration;
+use time::{format_description, OffsetDateTime};
+
+use self::mock_server::MockServer;
+use crate::config::{Config, TflConfig};
+use crate::events::EventBus;
+use crate::routes;
+use crate::store::{Store, StoreFairing};
+use crate::tfl::{LoadedStationDetails, PollTarget, Tfl};
+use crate::webhooks::WebhookFairing;
+
+/// Where the poller requests each kind of response from, given the modes that it's configured
+/// with.
+const MODES: &[&str] = &["tube", "dlr"];
+const LINE_STATUS_PATH: &str = "/Line/Mode/tube,dlr/Status";
+const DISRUPTION_PATH: &str = "/StopPoint/Mode/tube,dlr/Disruption";
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Reads a recorded TfL response from `tests/fixtures/tfl`.
+fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tfl")
+        .join(name);
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Failed to read fixture {}: {}", path.display(), err))
+}
+
+/// The API backed by an in-memory store, with a poller which is only run when the test asks.
+struct TestApp {
+    client: Client,
+    tfl: MockServer,
+    poller: Arc<Tfl>,
+}
+
+impl TestApp {
+    /// Starts the API, with TfL reporting no station disruptions until told otherwise.
+    async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Starts the API with changes to how TfL is polled. Retries, including webhook deliveries,
+    /// are quick unless changed.
+    async fn start_with(configure: impl FnOnce(&mut TflConfig)) -> Self {
+        let tfl = MockServer::start().await;
+        tfl.serve(DISRUPTION_PATH, "[]");
+        tfl.serve("/StopPoint/Mode/tube", fixture("stop_points_tube.json"));
+        tfl.serve("/StopPoint/Mode/dlr", fixture("stop_points_dlr.json"));
+
+        let mut tfl_config = TflConfig {
+            modes: MODES.iter().map(|mode| mode.to_string()).collect(),
+            base_url: tfl.base_url(),
+            initial_backoff_ms: 10,
+            max_backoff_ms: 1000,
+            ..Default::default()
+        };
+        configure(&mut tfl_config);
+        let events = EventBus::new();
+        let poller = Arc::new(Tfl::new(
+            Some("test-key".to_string()),
+            &tfl_config,
+            events.clone(),
+        ));
+        let station_details = Arc::new(LoadedStationDetails::new(Arc::new(poller.api.clone())));
+        let figment = Figment::from(rocket::Config::debug_default())
+            .merge(("log_level", "off"))
+            .merge(("store", json!({ "backend": "memory" })))
+            .merge(("admin_token", ADMIN_TOKEN))
+            .merge((
+                "webhooks",
+                json!({ "max_attempts": 3, "initial_backoff_ms": 10 }),
+            ));
+        let rocket = rocket::custom(figment)
+            .attach(AdHoc::config::<Config>())
+            .manage(events)
+            .attach(StoreFairing::new())
+            .attach(WebhookFairing::new())
+            .manage(poller.clone())
+            .manage(station_details)
+            .mount("/", routes::feeds::get_routes())
+            .mount("/api", routes::api::get_routes())
+            .mount("/api", routes::webhooks::get_routes());
+        let client = Client::tracked(rocket).await.unwrap();
+        TestApp {
+            client,
+            tfl,
+            poller,
+        }
+    }
+
+    /// Polls TfL, panicking if it fails.
+    async fn poll(&self) {
+        assert!(self.try_poll().await, "Polling should succeed");
+    }
+
+    /// Polls the lines and then the stations from TfL, returning whether both succeeded. Neither
+    /// does while polling is paused by the circuit breaker, and the stations aren't polled if the
+    /// lines fail.
+    async fn try_poll(&self) -> bool {
+        wait_for_next_second().await;
+        let mut store = self.client.rocket().state::<Store>().unwrap().clone();
+        self.poller.poll_target(PollTarget::Lines, &mut store).await
+            && self
+                .poller
+                .poll_target(PollTarget::Stations, &mut store)
+                .await
+    }
+
+    /// Polls one kind of status from TfL, returning whether it succeeded.
+    async fn try_poll_target(&self, target: PollTarget) -> bool {
+        wait_for_next_second().await;
+        let mut store = self.client.rocket().state::<Store>().unwrap().clone();
+        self.poller.poll_target(target, &mut store).await
+    }
+
+    /// Gets the line history for the hour either side of now.
+    async fn line_history(&self) -> Value {
+        self.get_history("/api/v1/history").await
+    }
+
+    /// Gets the line history for the hour either side of now, filtered by the query parameters.
+    async fn filtered_line_history(&self, query: &str) -> Value {
+        self.get_history(&format!("/api/v1/history?{}", query))
+            .await
+    }
+
+    /// Gets the station history for the hour either side of now.
+    async fn station_history(&self) -> Value {
+        self.get_history("/api/v1/station-history").await
+    }
+
+    async fn get_history(&self, path: &str) -> Value {
+        let now = OffsetDateTime::now_utc();
+        let format = |time: OffsetDateTime| {
+            time.format(&format_description::well_known::Rfc3339)
+                .unwrap()
+        };
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let uri = format!(
+            "{}{}from={}&to={}",
+            path,
+            separator,
+            format(now - 1.hours()),
+            format(now + 1.hours())
+        );
+        self.get_json(&uri).await
+    }
+
+    async fn get_json(&self, uri: &str) -> Value {
+        let response = self.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+        response.into_json().await.unwrap()
+    }
+
+    async fn get_text(&self, uri: &str) -> String {
+        let response = self.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+        response.into_string().await.unwrap()
+    }
+
+    /// Gets an admin endpoint, authorized with the admin token.
+    async fn get_admin_json(&self, uri: &str) -> Value {
+        let response = self
+            .client
+            .get(uri)
+            .header(admin_authorization())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+        response.into_json().await.unwrap()
+    }
+
+    /// Posts to an admin endpoint, authorized with the admin token.
+    async fn post_admin_json(&self, uri: &str, body: Value) -> Value {
+        let response = self
+            .client
+            .post(uri)
+            .header(admin_authorization())
+            .json(&body)
+            .dispatch()
+            .await;
+        assert!(response.status().class().is_success(), "POST {}", uri);
+        response.into_json().await.unwrap()
+    }
+}
+
+/// History rows are keyed by their start time in seconds, so each poll waits for the next second.
+async fn wait_for_next_second() {
+    let nanos = OffsetDateTime::now_utc().nanosecond() as u64;
+    sleep(Duration::from_nanos(1_000_000_000 - nanos)).await;
+}
+
+fn admin_authorization() -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+}
+
+/// Waits up to a few seconds for something done in the background, like delivering a webhook,
+/// panicking if it doesn't happen.
+async fn eventually(description: &str, mut done: impl FnMut() -> bool) {
+    for _ in 0..100 {
+        if done() {
+            return;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Timed out waiting for {}", description);
+}
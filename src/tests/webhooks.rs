@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use super::{eventually, fixture, MockServer, TestApp, LINE_STATUS_PATH};
+use crate::webhooks::{self, SIGNATURE_HEADER};
+
+const HOOK_PATH: &str = "/hook";
+const SECRET: &str = "webhook-secret";
+const SERVICE_UNAVAILABLE: &str = "503 Service Unavailable";
+
+/// Registers a webhook for the Central line, which is delivered to a new stand-in receiver.
+async fn register_webhook(app: &TestApp) -> MockServer {
+    let receiver = MockServer::start().await;
+    app.post_admin_json(
+        "/api/v1/webhooks",
+        json!({
+            "url": format!("{}{}", receiver.base_url(), HOOK_PATH),
+            "secret": SECRET,
+            "lines": ["central"],
+        }),
+    )
+    .await;
+    receiver
+}
+
+/// Polls the Central line into severe delays and back out again, which is a single change.
+async fn disrupt_and_recover(app: &TestApp) {
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_disrupted.json"));
+    app.poll().await;
+    app.tfl
+        .serve(LINE_STATUS_PATH, fixture("line_status_recovered.json"));
+    app.poll().await;
+}
+
+#[rocket::async_test]
+async fn delivers_signed_state_changes() {
+    let app = TestApp::start().await;
+    let receiver = register_webhook(&app).await;
+    receiver.serve(HOOK_PATH, "{}");
+    disrupt_and_recover(&app).await;
+    eventually("the webhook", || receiver.requests(HOOK_PATH) == 1).await;
+
+    let request = &receiver.received(HOOK_PATH)[0];
+    assert_eq!(request.headers["content-type"], "application/json");
+    assert_eq!(
+        request.headers[&SIGNATURE_HEADER.to_lowercase()],
+        webhooks::sign(SECRET, &request.body)
+    );
+    assert_ne!(
+        request.headers[&SIGNATURE_HEADER.to_lowercase()],
+        webhooks::sign("another-secret", &request.body)
+    );
+    let payload: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(payload["type"], "line");
+    assert_eq!(payload["line"], "central");
+    assert_eq!(payload["previous"][1]["status"], "SevereDelays");
+    assert_eq!(payload["current"][0]["status"], "GoodService");
+}
+
+#[rocket::async_test]
+async fn retries_failed_deliveries_with_backoff() {
+    let app = TestApp::start().await;
+    let receiver = register_webhook(&app).await;
+    receiver.serve(HOOK_PATH, "{}");
+    receiver.fail_times(HOOK_PATH, 2, SERVICE_UNAVAILABLE);
+    disrupt_and_recover(&app).await;
+    eventually("the webhook to be retried", || {
+        receiver.requests(HOOK_PATH) == 3
+    })
+    .await;
+
+    // The wait doubles after each failed attempt, starting from 10ms
+    let received = receiver.received(HOOK_PATH);
+    assert!(received[1].received_at - received[0].received_at >= Duration::from_millis(10));
+    assert!(received[2].received_at - received[1].received_at >= Duration::from_millis(20));
+    assert!(received
+        .iter()
+        .all(|request| request.body == received[0].body));
+
+    // Give it a chance to record a dead letter, which it shouldn't
+    rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(receiver.requests(HOOK_PATH), 3);
+    let dead_letters = app.get_admin_json("/api/v1/webhooks/dead-letters").await;
+    assert_eq!(dead_letters.as_array().unwrap().len(), 0);
+}
+
+#[rocket::async_test]
+async fn records_a_dead_letter_when_every_attempt_fails() {
+    let app = TestApp::start().await;
+    let receiver = register_webhook(&app).await;
+    receiver.fail(HOOK_PATH, SERVICE_UNAVAILABLE, &[]);
+    disrupt_and_recover(&app).await;
+
+    let mut dead_letters = Value::Null;
+    for _ in 0..100 {
+        dead_letters = app.get_admin_json("/api/v1/webhooks/dead-letters").await;
+        if !dead_letters.as_array().unwrap().is_empty() {
+            break;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let dead_letters = dead_letters.as_array().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(receiver.requests(HOOK_PATH), 3);
+    let dead_letter = &dead_letters[0];
+    assert_eq!(dead_letter["attempts"], 3);
+    assert_eq!(
+        dead_letter["url"],
+        format!("{}{}", receiver.base_url(), HOOK_PATH)
+    );
+    assert!(dead_letter["error"].as_str().unwrap().contains("503"));
+    assert_eq!(dead_letter["payload"]["line"], "central");
+}
@@ -1,48 +1,181 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
-use reqwest::RequestBuilder;
-use rocket::{futures::TryFutureExt, tokio::try_join};
+use log::{debug, warn};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use rocket::futures::future::try_join_all;
+use rocket::futures::stream::{self, StreamExt};
+use rocket::tokio::time::sleep;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use time::{format_description, OffsetDateTime};
 
 use super::parser::{StopPointDetails, StopPointModeResponse};
+use crate::config::TflConfig;
 
-const LINE_STATUS_API_URI: &'static str =
-    "https://api.tfl.gov.uk/Line/Mode/tube,dlr,overground,elizabeth-line/Status";
-const STATION_STATUS_API_URI: &'static str =
-    "https://api.tfl.gov.uk/StopPoint/Mode/tube,dlr,overground,elizabeth-line/Disruption";
-const TUBE_STATION_DETAILS_API_URI: &'static str = "https://api.tfl.gov.uk/StopPoint/Mode/tube";
-const RAIL_STATION_DETAILS_API_URI: &'static str =
-    "https://api.tfl.gov.uk/StopPoint/Mode/dlr,overground,elizabeth-line";
+/// Modes with too many lines or stops to load along with the others, which are loaded in
+/// batches instead.
+const BATCHED_MODES: &[&str] = &["bus"];
+/// How many lines of a batched mode to request the status of at once.
+const LINES_PER_REQUEST: usize = 50;
+/// How many line status requests to make at the same time, so that batched modes don't get us
+/// rate limited.
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+/// How long to keep the lines of a batched mode before asking TfL for them again. They only
+/// change when routes do.
+const LINE_IDS_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// TfL pages the stop points of batched modes, with 1000 per page. This is a safety limit.
+const MAX_STOP_POINT_PAGES: u32 = 100;
 
 #[derive(Clone)]
 pub struct Api {
     client: reqwest::Client,
     api_key: Option<String>,
+    base_url: String,
+    modes: Vec<String>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    /// The ids of each batched mode's lines.
+    line_ids: Arc<Mutex<HashMap<String, LineIds>>>,
+}
+
+#[derive(Clone)]
+struct LineIds {
+    loaded: Instant,
+    ids: Vec<String>,
+}
+
+/// The status of each line that could be loaded. Requests which fail are left out, so that one
+/// failed batch doesn't lose the status of every other line.
+pub struct LineStatusResponse {
+    pub lines: HashMap<String, Value>,
+    /// Whether some requests failed, so that lines may be missing.
+    pub incomplete: bool,
 }
 
 impl Api {
-    pub fn new(api_key: Option<String>) -> Self {
+    pub fn new(api_key: Option<String>, config: &TflConfig) -> Self {
         if api_key.is_none() {
             log::warn!("No TFL API key provided");
         }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .user_agent(&config.user_agent)
+            .build()
+            .unwrap();
         Api {
-            client: reqwest::Client::new(),
+            client,
             api_key,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            modes: config.modes.clone(),
+            max_attempts: config.max_attempts,
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            line_ids: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    pub async fn load_line_status(&self) -> Result<HashMap<String, Value>, ApiError> {
-        let resp = self
-            .add_api_key(self.client.get(LINE_STATUS_API_URI))
-            .send()
-            .await?;
-        let tfl_status = resp.json::<Vec<Value>>().await?;
-        let status = tfl_status
-            .into_iter()
-            .filter_map(|value| Some((value.get("id")?.as_str()?.to_string(), value)))
-            .collect::<HashMap<String, Value>>();
-        Ok(status)
+    /// Loads the status of every line, failing only if none of it could be loaded.
+    pub async fn load_line_status(&self) -> Result<LineStatusResponse, ApiError> {
+        let mut uris = Vec::new();
+        let mut errors = Vec::new();
+        for modes in self.mode_groups() {
+            if !is_batched(&modes) {
+                uris.push(format!("{}/Line/Mode/{}/Status", self.base_url, modes));
+                continue;
+            }
+            match self.load_line_ids(&modes).await {
+                Ok(ids) => {
+                    for ids in ids.chunks(LINES_PER_REQUEST) {
+                        uris.push(format!("{}/Line/{}/Status", self.base_url, ids.join(",")));
+                    }
+                }
+                Err(err) => {
+                    warn!("Failed to load the lines of mode {}: {:?}", modes, err);
+                    errors.push(err);
+                }
+            }
+        }
+        let responses = stream::iter(uris)
+            .map(|uri| async move {
+                let response = self.get_json::<Vec<Value>>(self.client.get(&uri)).await;
+                (uri, response)
+            })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .collect::<Vec<_>>()
+            .await;
+        let mut lines = HashMap::new();
+        let mut loaded_any = false;
+        for (uri, response) in responses {
+            match response {
+                Ok(values) => {
+                    loaded_any = true;
+                    lines.extend(
+                        values.into_iter().filter_map(|value| {
+                            Some((value.get("id")?.as_str()?.to_string(), value))
+                        }),
+                    );
+                }
+                Err(err) => {
+                    warn!("Failed to load line status from {}: {:?}", uri, err);
+                    errors.push(err);
+                }
+            }
+        }
+        let incomplete = !errors.is_empty();
+        if !loaded_any {
+            // Prefer an error that says how long to wait, so that the breaker can wait that long
+            if let Some(err) = errors.into_iter().max_by_key(ApiError::retry_after) {
+                return Err(err);
+            }
+        }
+        Ok(LineStatusResponse { lines, incomplete })
+    }
+
+    /// Gets the ids of a batched mode's lines, which are reused for a day once loaded. If they
+    /// can't be reloaded, the old ones are used until they can.
+    async fn load_line_ids(&self, mode: &str) -> Result<Vec<String>, ApiError> {
+        let cached = self.line_ids.lock().unwrap().get(mode).cloned();
+        if let Some(cached) = &cached {
+            if cached.loaded.elapsed() < LINE_IDS_MAX_AGE {
+                return Ok(cached.ids.clone());
+            }
+        }
+        let lines = self
+            .get_json::<Vec<Value>>(
+                self.client
+                    .get(format!("{}/Line/Mode/{}", self.base_url, mode)),
+            )
+            .await;
+        match (lines, cached) {
+            (Ok(lines), _) => {
+                let ids = lines
+                    .iter()
+                    .filter_map(|line| Some(line.get("id")?.as_str()?.to_string()))
+                    .collect::<Vec<_>>();
+                let mut line_ids = self.line_ids.lock().unwrap();
+                line_ids.insert(
+                    mode.to_string(),
+                    LineIds {
+                        loaded: Instant::now(),
+                        ids: ids.clone(),
+                    },
+                );
+                Ok(ids)
+            }
+            (Err(err), Some(LineIds { ids, .. })) => {
+                warn!(
+                    "Failed to reload the lines of mode {}, using the old ones: {:?}",
+                    mode, err
+                );
+                Ok(ids)
+            }
+            (Err(err), None) => Err(err),
+        }
     }
 
     fn add_api_key(&self, request: RequestBuilder) -> RequestBuilder {
@@ -53,34 +186,85 @@ impl Api {
         }
     }
 
+    /// Sends a request, retrying with exponential backoff and jitter while it fails in a way that
+    /// might not happen again. TfL's `Retry-After` is waited out if it's within the maximum
+    /// backoff, otherwise the request fails straight away.
+    async fn get_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ApiError> {
+        let mut backoff = self.initial_backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let attempt = request
+                .try_clone()
+                .expect("GET requests have no body to stream");
+            let error = match self.try_get_json(attempt).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if !error.is_transient() || attempts >= self.max_attempts {
+                return Err(error);
+            }
+            let delay = match error.retry_after() {
+                Some(retry_after) if retry_after > self.max_backoff => return Err(error),
+                Some(retry_after) => retry_after,
+                None => jitter(backoff),
+            };
+            debug!(
+                "TFL request failed (attempt {}), retrying in {:?}: {:?}",
+                attempts, delay, error
+            );
+            sleep(delay).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    async fn try_get_json<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T, ApiError> {
+        let resp = self.add_api_key(request).send().await?;
+        match resp.status() {
+            StatusCode::TOO_MANY_REQUESTS => Err(ApiError::RateLimited(retry_after(&resp))),
+            status if !status.is_success() => Err(ApiError::Status(status)),
+            _ => Ok(resp.json::<T>().await?),
+        }
+    }
+
+    /// The configured modes, grouped so that each group can be requested at once: all the
+    /// unbatched modes together, and each batched mode on its own.
+    fn mode_groups(&self) -> Vec<String> {
+        let (batched, unbatched): (Vec<_>, Vec<_>) =
+            self.modes.iter().partition(|mode| is_batched(mode));
+        let mut groups = batched.into_iter().cloned().collect::<Vec<_>>();
+        if !unbatched.is_empty() {
+            groups.insert(0, unbatched.into_iter().join(","));
+        }
+        groups
+    }
+
     pub async fn load_station_status(&self) -> Result<HashMap<String, Vec<Value>>, ApiError> {
-        let resp = self
-            .add_api_key(self.client.get(STATION_STATUS_API_URI))
-            .send()
-            .await?;
-        let tfl_status = resp.json::<Vec<Value>>().await?;
-        let status = tfl_status
+        let responses = try_join_all(self.mode_groups().into_iter().map(|modes| {
+            self.get_json::<Vec<Value>>(self.client.get(format!(
+                "{}/StopPoint/Mode/{}/Disruption",
+                self.base_url, modes
+            )))
+        }))
+        .await?;
+        let status = responses
             .into_iter()
+            .flatten()
             .filter_map(|value| Some((value.get("stationAtcoCode")?.as_str()?.to_string(), value)))
             .into_group_map();
         Ok(status)
     }
 
     pub async fn load_station_details(&self) -> Result<Vec<StopPointDetails>, ApiError> {
-        let tube_req = self
-            .add_api_key(self.client.get(TUBE_STATION_DETAILS_API_URI))
-            .send()
-            .and_then(|resp| resp.json::<StopPointModeResponse>());
-        let rail_req = self
-            .add_api_key(self.client.get(RAIL_STATION_DETAILS_API_URI))
-            .send()
-            .and_then(|resp| resp.json::<StopPointModeResponse>());
-        let (tube_resp, rail_resp) = try_join!(tube_req, rail_req)?;
-
-        Ok(tube_resp
-            .stop_points
+        let responses =
+            try_join_all(self.modes.iter().map(|mode| self.load_stop_points(mode))).await?;
+
+        Ok(responses
             .into_iter()
-            .chain(rail_resp.stop_points.into_iter())
+            .flatten()
             // Filter to only include stations where id and stationNaptan are equal
             .filter(|point| {
                 // Keep the station if stationNaptan is equal to id, or if stationNaptan is None
@@ -89,13 +273,89 @@ impl Api {
                     None => false, // Skip stations without a stationNaptan
                 }
             })
+            // Stations served by several modes are listed once for each
+            .unique_by(|point| point.id.clone())
             .collect())
     }
+
+    /// Loads the stop points of one mode, a page at a time if it's batched.
+    async fn load_stop_points(&self, mode: &str) -> Result<Vec<StopPointDetails>, ApiError> {
+        let uri = format!("{}/StopPoint/Mode/{}", self.base_url, mode);
+        if !is_batched(mode) {
+            let resp = self
+                .get_json::<StopPointModeResponse>(self.client.get(&uri))
+                .await?;
+            return Ok(resp.stop_points);
+        }
+        let mut stop_points = Vec::new();
+        for page in 1..=MAX_STOP_POINT_PAGES {
+            let resp = self
+                .get_json::<StopPointModeResponse>(self.client.get(&uri).query(&[("page", page)]))
+                .await?;
+            if resp.stop_points.is_empty() {
+                break;
+            }
+            stop_points.extend(resp.stop_points);
+            if resp.total.is_some_and(|total| stop_points.len() >= total) {
+                break;
+            }
+        }
+        Ok(stop_points)
+    }
+}
+
+fn is_batched(mode: &str) -> bool {
+    BATCHED_MODES.contains(&mode)
+}
+
+/// Takes a random amount of up to half off a backoff, so that retries are spread out.
+fn jitter(backoff: Duration) -> Duration {
+    backoff.mul_f64(1.0 - rand::random::<f64>() / 2.0)
+}
+
+/// How long a response asks us to wait before trying again, which TfL can give in seconds or as
+/// an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = OffsetDateTime::parse(value, &format_description::well_known::Rfc2822).ok()?;
+    Some(Duration::try_from(date - OffsetDateTime::now_utc()).unwrap_or(Duration::ZERO))
 }
 
 #[derive(Debug)]
 pub enum ApiError {
     Reqwest(reqwest::Error),
+    /// TfL responded with an error status.
+    Status(StatusCode),
+    /// TfL is rate limiting us, and may have said how long to wait.
+    RateLimited(Option<Duration>),
+}
+
+impl ApiError {
+    /// Whether trying again might succeed. Responses that can't be read aren't retried, since TfL
+    /// tends to keep sending the same thing.
+    fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Reqwest(err) => err.is_timeout() || err.is_connect(),
+            ApiError::Status(status) => status.is_server_error(),
+            ApiError::RateLimited(_) => true,
+        }
+    }
+
+    /// How long TfL asked us to wait before trying again, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RateLimited(retry_after) => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 impl From<reqwest::Error> for ApiError {
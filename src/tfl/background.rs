@@ -1,61 +1,283 @@
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use log::{debug, warn};
-use rocket::tokio::{self, try_join};
+use rocket::tokio::join;
+use rocket::tokio::time::{sleep_until, Instant};
 use serde_json::Value;
+use time::OffsetDateTime;
 
 use super::api::{Api, ApiError};
-use crate::store::{ConnectionError, SetStatusError, Store};
+use super::breaker::{CircuitBreaker, PollerHealth};
+use super::parser::{try_parse_line_status, try_parse_station_status};
+use super::schedule::{self, PollTarget};
+use crate::config::{PollingConfig, TflConfig};
+use crate::events::{EventBus, StatusEvent};
+use crate::store::{ConnectionError, SetStatusError, Store, StoreConnection};
+use crate::types::{CurrentLineStatus, CurrentStatus, StationStatus};
 
 pub struct Tfl {
     pub api: Api,
+    polling: PollingConfig,
+    gap_after: time::Duration,
+    polled: Mutex<PolledStatus>,
+    current_status: RwLock<Option<Arc<CurrentStatus>>>,
+    events: EventBus,
+    breaker: Mutex<CircuitBreaker>,
+    store_health: Mutex<StoreHealth>,
 }
 
+/// What the health endpoint reports about recording polled status in the store. Failing to do so
+/// doesn't affect the circuit breaker, since TfL did respond.
+#[derive(Debug, Clone, Default)]
+pub struct StoreHealth {
+    pub last_success: Option<OffsetDateTime>,
+    pub last_failure: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+}
+
+/// The line and station status from their last successful polls, and when each was loaded.
+#[derive(Default)]
+struct PolledStatus {
+    lines: Option<(OffsetDateTime, HashMap<String, CurrentLineStatus>)>,
+    stations: Option<(OffsetDateTime, HashMap<String, Vec<StationStatus>>)>,
+}
+
+/// Fields which change without the status itself changing, so they don't start a new history
+/// row. Validity periods are still stored, as they were when each row started.
 const IGNORED_FIELDS: &[&str] = &["validityPeriods", "created"];
 
 impl Tfl {
-    pub fn new(api_key: Option<String>) -> Self {
+    pub fn new(api_key: Option<String>, config: &TflConfig, events: EventBus) -> Self {
         Tfl {
-            api: Api::new(api_key),
+            api: Api::new(api_key, config),
+            polling: config.polling.clone(),
+            gap_after: time::Duration::seconds(config.gap_after_secs as i64),
+            polled: Mutex::new(PolledStatus::default()),
+            current_status: RwLock::new(None),
+            events,
+            breaker: Mutex::new(CircuitBreaker::new(
+                config.breaker_threshold,
+                Duration::from_secs(config.breaker_cooldown_secs),
+            )),
+            store_health: Mutex::new(StoreHealth::default()),
         }
     }
 
-    pub async fn start_polling(self: Arc<Self>, mut store: Store) {
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    /// The status from the last successful polls, or `None` if we haven't managed to load both
+    /// the lines and stations yet.
+    pub fn current_status(&self) -> Option<Arc<CurrentStatus>> {
+        self.current_status.read().unwrap().clone()
+    }
+
+    /// How far apart successful polls can be before there's a gap in the history.
+    pub fn gap_after(&self) -> time::Duration {
+        self.gap_after
+    }
+
+    /// Whether polling is paused, and how the last polls went.
+    pub fn health(&self) -> PollerHealth {
+        self.breaker.lock().unwrap().health()
+    }
+
+    /// How the last attempts to record polled status in the store went.
+    pub fn store_health(&self) -> StoreHealth {
+        self.store_health.lock().unwrap().clone()
+    }
+
+    pub async fn start_polling(self: Arc<Self>, store: Store) {
+        join!(
+            self.clone()
+                .poll_periodically(PollTarget::Lines, store.clone()),
+            self.poll_periodically(PollTarget::Stations, store),
+        );
+    }
+
+    /// Polls a target for ever, at the interval given by the schedule after each poll.
+    async fn poll_periodically(self: Arc<Self>, target: PollTarget, mut store: Store) {
         loop {
-            interval.tick().await;
-            match self.update_status(&mut store).await {
-                Ok(()) => debug!("Updated TFL status"),
-                Err(PollError::ApiError(err)) => warn!("Error reloading TFL status: {:?}", err),
-                Err(PollError::ConnectionError(err)) => {
-                    warn!(
-                        "Failed to acquire DB connection while reloading TFL status: {:?}",
-                        err
-                    )
-                }
-                Err(PollError::SetStatusError(err)) => {
-                    warn!("Failed to set TFL status in DB: {:?}", err)
+            let started = Instant::now();
+            self.poll_target(target, &mut store).await;
+            let interval = {
+                let polled = self.polled.lock().unwrap();
+                let lines = polled.lines.as_ref().map(|(_, lines)| lines);
+                schedule::interval(&self.polling, target, OffsetDateTime::now_utc(), lines)
+            };
+            debug!("Polling TFL {:?} again in {:?}", target, interval);
+            sleep_until(started + interval).await;
+        }
+    }
+
+    /// Updates one kind of status, returning whether it was updated.
+    pub async fn poll_target(&self, target: PollTarget, store: &mut Store) -> bool {
+        match target {
+            PollTarget::Lines => self.poll_with(self.update_line_status(store)).await,
+            PollTarget::Stations => self.poll_with(self.update_station_status(store)).await,
+        }
+    }
+
+    /// Runs an update unless polling is paused by the circuit breaker, returning whether it
+    /// succeeded. Only failures to load from TfL count towards opening the breaker, and failures to
+    /// write to the store are reported separately.
+    async fn poll_with(&self, update: impl Future<Output = Result<(), PollError>>) -> bool {
+        let now = OffsetDateTime::now_utc();
+        if !self.breaker.lock().unwrap().allow(now) {
+            debug!("Skipping TFL poll while polling is paused");
+            return false;
+        }
+        let result = update.await;
+        let now = OffsetDateTime::now_utc();
+        match result {
+            Ok(()) => {
+                debug!("Updated TFL status");
+                self.breaker.lock().unwrap().record_success(now);
+                self.store_health.lock().unwrap().last_success = Some(now);
+                true
+            }
+            Err(PollError::Api(err)) => {
+                let mut breaker = self.breaker.lock().unwrap();
+                breaker.record_failure(now, format!("{:?}", err), err.retry_after());
+                false
+            }
+            // TfL responded to these, so the breaker is left as it was
+            Err(PollError::Connection(err)) => {
+                warn!(
+                    "Failed to acquire DB connection while reloading TFL status: {}",
+                    err
+                );
+                self.record_store_failure(now, err.to_string());
+                false
+            }
+            Err(PollError::SetStatus(err)) => {
+                warn!("Failed to set TFL status in DB: {}", err);
+                self.record_store_failure(now, err.to_string());
+                false
+            }
+        }
+    }
+
+    fn record_store_failure(&self, now: OffsetDateTime, error: String) {
+        let mut store_health = self.store_health.lock().unwrap();
+        store_health.last_failure = Some(now);
+        store_health.last_error = Some(error);
+    }
+
+    /// Loads the latest line status from TfL, then records it and publishes the changes.
+    async fn update_line_status(&self, store: &mut Store) -> Result<(), PollError> {
+        let response = self.api.load_line_status().await?;
+
+        // Update the in-memory status before writing to the store, so that it stays live even if
+        // the database is unavailable
+        let mut lines = parse_current_lines(&response.lines);
+        self.update_current_status(|polled| {
+            // Lines which couldn't be loaded this time keep their last known status
+            if let (true, Some((_, previous))) = (response.incomplete, &polled.lines) {
+                for (line, status) in previous {
+                    lines.entry(line.clone()).or_insert_with(|| status.clone());
                 }
             }
+            polled.lines = Some((OffsetDateTime::now_utc(), lines));
+        });
+
+        // Lines which are missing are left as they were in the store
+        let mut connection = store.get_connection().await?;
+        let transitions = connection
+            .set_line_status(response.lines, &should_update_line)
+            .await?;
+        self.record_poll(&mut connection, PollTarget::Lines).await?;
+
+        // Only publish once the changes have been committed
+        for transition in transitions {
+            self.events.publish(StatusEvent::Line(transition));
         }
+        Ok(())
     }
 
-    async fn update_status(&self, store: &mut Store) -> Result<(), PollError> {
-        let line_status_future = self.api.load_line_status();
-        let station_status_future = self.api.load_station_status();
-        let (line_status, station_status) = try_join!(line_status_future, station_status_future)?;
+    /// Loads the latest station status from TfL, then records it and publishes the changes.
+    async fn update_station_status(&self, store: &mut Store) -> Result<(), PollError> {
+        let station_status = self.api.load_station_status().await?;
+
+        let stations = parse_current_stations(&station_status);
+        self.update_current_status(|polled| {
+            polled.stations = Some((OffsetDateTime::now_utc(), stations));
+        });
 
         let mut connection = store.get_connection().await?;
-        connection
-            .set_line_status(line_status, should_update_line)
+        let transitions = connection
+            .set_station_status(station_status, &should_update_station)
             .await?;
-        connection
-            .set_station_status(station_status, should_update_station)
+        self.record_poll(&mut connection, PollTarget::Stations)
             .await?;
+        for transition in transitions {
+            self.events.publish(StatusEvent::Station(transition));
+        }
+        Ok(())
+    }
 
+    /// Records that a target has been polled, and any gap since it last was.
+    async fn record_poll(
+        &self,
+        connection: &mut StoreConnection,
+        target: PollTarget,
+    ) -> Result<(), SetStatusError> {
+        let gap = connection
+            .record_poll(target.name(), OffsetDateTime::now_utc(), self.gap_after)
+            .await?;
+        if let Some(gap) = gap {
+            warn!(
+                "Recorded a gap in collecting {} since {}",
+                target.name(),
+                gap.start_time
+            );
+        }
         Ok(())
     }
+
+    /// Replaces part of the in-memory status. It's only published once both the lines and
+    /// stations have been loaded, and is as old as the older of the two.
+    fn update_current_status(&self, update: impl FnOnce(&mut PolledStatus)) {
+        let mut polled = self.polled.lock().unwrap();
+        update(&mut polled);
+        if let (Some((lines_updated, lines)), Some((stations_updated, stations))) =
+            (&polled.lines, &polled.stations)
+        {
+            let current_status = CurrentStatus {
+                updated: *lines_updated.min(stations_updated),
+                lines: lines.clone(),
+                stations: stations.clone(),
+            };
+            *self.current_status.write().unwrap() = Some(Arc::new(current_status));
+        }
+    }
+}
+
+fn parse_current_lines(line_status: &HashMap<String, Value>) -> HashMap<String, CurrentLineStatus> {
+    line_status
+        .iter()
+        .filter_map(|(line, value)| {
+            let (metadata, statuses) = try_parse_line_status(line, value)?;
+            Some((
+                line.clone(),
+                CurrentLineStatus {
+                    mode: metadata.mode,
+                    statuses,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn parse_current_stations(
+    station_status: &HashMap<String, Vec<Value>>,
+) -> HashMap<String, Vec<StationStatus>> {
+    station_status
+        .iter()
+        .filter_map(|(station, values)| {
+            Some((station.clone(), try_parse_station_status(station, values)?))
+        })
+        .collect()
 }
 
 fn should_update_line(old: &Value, new: &Value) -> bool {
@@ -89,7 +311,7 @@ fn should_update_line(old: &Value, new: &Value) -> bool {
     }
 }
 
-fn should_update_station(old: &Vec<Value>, new: &Vec<Value>) -> bool {
+fn should_update_station(old: &[Value], new: &[Value]) -> bool {
     if old.len() != new.len() {
         return true;
     }
@@ -127,26 +349,26 @@ fn should_update_station(old: &Vec<Value>, new: &Vec<Value>) -> bool {
 }
 
 #[derive(Debug)]
-enum PollError {
-    ApiError(ApiError),
-    ConnectionError(ConnectionError),
-    SetStatusError(SetStatusError),
+pub enum PollError {
+    Api(ApiError),
+    Connection(ConnectionError),
+    SetStatus(SetStatusError),
 }
 
 impl From<ApiError> for PollError {
     fn from(err: ApiError) -> Self {
-        PollError::ApiError(err)
+        PollError::Api(err)
     }
 }
 
 impl From<ConnectionError> for PollError {
     fn from(err: ConnectionError) -> Self {
-        PollError::ConnectionError(err)
+        PollError::Connection(err)
     }
 }
 
 impl From<SetStatusError> for PollError {
     fn from(err: SetStatusError) -> Self {
-        PollError::SetStatusError(err)
+        PollError::SetStatus(err)
     }
 }
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use log::{info, warn};
+use time::OffsetDateTime;
+
+/// Pauses polling after repeated failures, so that a TfL outage or rate limit isn't made worse by
+/// us, and then tries a single poll to see whether it has recovered.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: BreakerState,
+    consecutive_failures: u32,
+    last_success: Option<OffsetDateTime>,
+    last_failure: Option<OffsetDateTime>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Polling as normal.
+    Closed,
+    /// Polling is paused until the given time.
+    Open { until: OffsetDateTime },
+    /// The pause is over, and the next poll decides whether to close or open the breaker again.
+    HalfOpen,
+}
+
+/// What the health endpoint reports about polling.
+#[derive(Debug, Clone)]
+pub struct PollerHealth {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub last_success: Option<OffsetDateTime>,
+    pub last_failure: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            last_success: None,
+            last_failure: None,
+            last_error: None,
+        }
+    }
+
+    /// Whether to poll now, which moves an open breaker to half-open once its pause is over.
+    pub fn allow(&mut self, now: OffsetDateTime) -> bool {
+        match self.state {
+            BreakerState::Open { until } if now < until => false,
+            BreakerState::Open { .. } => {
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+        }
+    }
+
+    pub fn record_success(&mut self, now: OffsetDateTime) {
+        if self.state != BreakerState::Closed {
+            info!(
+                "TFL polling recovered after {} failed polls",
+                self.consecutive_failures
+            );
+        }
+        self.state = BreakerState::Closed;
+        self.consecutive_failures = 0;
+        self.last_success = Some(now);
+    }
+
+    /// Records a failed poll, opening the breaker if there have been too many in a row, if it was
+    /// half-open, or if TfL asked us to wait before trying again.
+    pub fn record_failure(
+        &mut self,
+        now: OffsetDateTime,
+        error: String,
+        retry_after: Option<Duration>,
+    ) {
+        self.consecutive_failures += 1;
+        self.last_failure = Some(now);
+        let pause = if self.state == BreakerState::HalfOpen
+            || self.consecutive_failures >= self.threshold
+        {
+            Some(self.cooldown.max(retry_after.unwrap_or_default()))
+        } else {
+            retry_after.filter(|retry_after| !retry_after.is_zero())
+        };
+        match pause {
+            Some(pause) => {
+                let until = now + pause;
+                warn!(
+                    "Pausing TFL polling until {} after {} failed polls: {}",
+                    until, self.consecutive_failures, error
+                );
+                self.state = BreakerState::Open { until };
+            }
+            None => warn!("Error reloading TFL status: {}", error),
+        }
+        self.last_error = Some(error);
+    }
+
+    pub fn health(&self) -> PollerHealth {
+        PollerHealth {
+            state: self.state,
+            consecutive_failures: self.consecutive_failures,
+            last_success: self.last_success,
+            last_failure: self.last_failure,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::ext::NumericalDuration;
+
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(3, Duration::from_secs(300))
+    }
+
+    fn fail(breaker: &mut CircuitBreaker, now: OffsetDateTime) {
+        assert!(breaker.allow(now));
+        breaker.record_failure(now, "Status(503)".to_string(), None);
+    }
+
+    #[test]
+    fn opens_after_too_many_failures_in_a_row() {
+        let mut breaker = breaker();
+        let now = OffsetDateTime::now_utc();
+        fail(&mut breaker, now);
+        breaker.record_success(now);
+        fail(&mut breaker, now);
+        fail(&mut breaker, now);
+        assert_eq!(breaker.health().state, BreakerState::Closed);
+
+        fail(&mut breaker, now);
+        assert_eq!(
+            breaker.health().state,
+            BreakerState::Open {
+                until: now + 300.seconds()
+            }
+        );
+        assert_eq!(breaker.health().consecutive_failures, 3);
+        assert!(!breaker.allow(now + 299.seconds()));
+    }
+
+    #[test]
+    fn tries_a_single_poll_after_the_cooldown() {
+        let mut breaker = breaker();
+        let now = OffsetDateTime::now_utc();
+        for _ in 0..3 {
+            fail(&mut breaker, now);
+        }
+
+        // Failing while half-open pauses again straight away
+        let retry = now + 300.seconds();
+        fail(&mut breaker, retry);
+        assert_eq!(
+            breaker.health().state,
+            BreakerState::Open {
+                until: retry + 300.seconds()
+            }
+        );
+
+        let retry = retry + 300.seconds();
+        assert!(breaker.allow(retry));
+        assert_eq!(breaker.health().state, BreakerState::HalfOpen);
+        breaker.record_success(retry);
+        let health = breaker.health();
+        assert_eq!(health.state, BreakerState::Closed);
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(health.last_success, Some(retry));
+        assert_eq!(health.last_failure, Some(now + 300.seconds()));
+        assert_eq!(health.last_error.as_deref(), Some("Status(503)"));
+    }
+
+    #[test]
+    fn waits_as_long_as_tfl_asks() {
+        let mut breaker = breaker();
+        let now = OffsetDateTime::now_utc();
+        breaker.record_failure(
+            now,
+            "RateLimited".to_string(),
+            Some(Duration::from_secs(120)),
+        );
+        assert_eq!(
+            breaker.health().state,
+            BreakerState::Open {
+                until: now + 120.seconds()
+            }
+        );
+
+        // TfL can also make it wait for longer than the cooldown
+        assert!(breaker.allow(now + 120.seconds()));
+        breaker.record_failure(
+            now + 120.seconds(),
+            "RateLimited".to_string(),
+            Some(Duration::from_secs(600)),
+        );
+        assert_eq!(
+            breaker.health().state,
+            BreakerState::Open {
+                until: now + 720.seconds()
+            }
+        );
+    }
+}
@@ -3,6 +3,7 @@ use rocket::tokio::spawn;
 use rocket::{Build, Orbit, Rocket};
 use std::sync::Arc;
 
+use crate::events::EventBus;
 use crate::store::Store;
 
 use super::{LoadedStationDetails, Tfl};
@@ -26,14 +27,15 @@ impl Fairing for TflFairing {
 
     async fn on_ignite(&self, mut rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
         let config = rocket.state::<crate::config::Config>().unwrap();
-        
+
         // Create the Tfl instance that will be shared
-        let tfl = Arc::new(Tfl::new(config.tfl_api_key.clone()));
-        
-        // Create station details handler (loads asynchronously) 
+        let events = rocket.state::<EventBus>().unwrap().clone();
+        let tfl = Arc::new(Tfl::new(config.tfl_api_key.clone(), &config.tfl, events));
+
+        // Create station details handler (loads asynchronously)
         let api_ref = Arc::new(tfl.api.clone());
         let station_details = Arc::new(LoadedStationDetails::new(api_ref));
-        
+
         // Add both to rocket state
         rocket = rocket.manage(station_details);
         Ok(rocket.manage(tfl))
@@ -43,17 +45,17 @@ impl Fairing for TflFairing {
         // Here we use the already created Tfl instance
         let tfl = rocket.state::<Arc<Tfl>>().unwrap().clone();
         let store = rocket.state::<Store>().unwrap().clone();
-        
+
         // Start polling for updates
         spawn(async move {
             tfl.start_polling(store).await;
         });
-        
+
         // Also kick off station details loading
         if let Some(station_details) = rocket.state::<Arc<LoadedStationDetails>>() {
             // Clone the Arc to avoid lifetime issues
             let details = station_details.clone();
-            
+
             // Spawn a task to load station details in the background
             spawn(async move {
                 let _ = details.get_details().await;
@@ -1,11 +1,18 @@
 mod api;
 mod background;
+mod breaker;
 mod fairing;
 mod parser;
+mod schedule;
 mod stationdetails;
 
-pub use background::Tfl;
+pub use background::{StoreHealth, Tfl};
+pub use breaker::{BreakerState, PollerHealth};
 pub use fairing::TflFairing;
+pub use parser::strip_line_status;
+pub use parser::strip_station_status;
 pub use parser::try_parse_line_status;
 pub use parser::try_parse_station_status;
+pub use parser::PARSER_VERSION;
+pub use schedule::PollTarget;
 pub use stationdetails::LoadedStationDetails;
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
-
 use itertools::Itertools;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
+use time::OffsetDateTime;
 
-use crate::types::{LineMetadata, LineState, LineStatus, StationState, StationStatus};
+use crate::types::{
+    Disruption, LineMetadata, LineState, LineStatus, StationState, StationStatus, ValidityPeriod,
+};
 
 #[derive(Deserialize, Debug, Clone)]
 struct TflLineStatusWrapper {
@@ -19,8 +20,56 @@ struct TflLineStatus {
     #[serde(rename = "statusSeverity")]
     pub status_severity: i32,
     pub reason: Option<String>,
+    #[serde(
+        rename = "validityPeriods",
+        default,
+        deserialize_with = "skip_invalid_periods"
+    )]
+    pub validity_periods: Vec<TflValidityPeriod>,
+    pub disruption: Option<TflDisruption>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TflValidityPeriod {
+    #[serde(rename = "fromDate", with = "time::serde::rfc3339")]
+    pub from_date: OffsetDateTime,
+    #[serde(rename = "toDate", with = "time::serde::rfc3339")]
+    pub to_date: OffsetDateTime,
+    #[serde(rename = "isNow", default)]
+    pub is_now: bool,
+}
+
+/// Parses each validity period on its own, leaving out any with missing or malformed dates rather
+/// than failing to parse the whole line.
+fn skip_invalid_periods<'de, D>(deserializer: D) -> Result<Vec<TflValidityPeriod>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Option::<Vec<Value>>::deserialize(deserializer)?.unwrap_or_default();
+    Ok(values
+        .into_iter()
+        .filter_map(|value| {
+            serde_json::from_value(value)
+                .map_err(|err| log::warn!("Skipping invalid TFL validity period: {:?}", err))
+                .ok()
+        })
+        .collect())
 }
 
+#[derive(Deserialize, Debug, Clone)]
+struct TflDisruption {
+    pub category: Option<String>,
+    pub description: Option<String>,
+    #[serde(rename = "additionalInfo")]
+    pub additional_info: Option<String>,
+    #[serde(rename = "closureText")]
+    pub closure_text: Option<String>,
+}
+
+/// Version of the parsing logic below. Bump this whenever the way that raw statuses are parsed
+/// changes, so that the stored parsed statuses are regenerated.
+pub const PARSER_VERSION: i64 = 4;
+
 pub fn try_parse_line_status(
     line_id: &str,
     value: &Value,
@@ -36,6 +85,21 @@ pub fn try_parse_line_status(
         .map(|s| LineStatus {
             status: from_tfl_line_status(s.status_severity),
             reason: s.reason,
+            validity_periods: s
+                .validity_periods
+                .into_iter()
+                .map(|period| ValidityPeriod {
+                    from: period.from_date,
+                    to: period.to_date,
+                    is_now: period.is_now,
+                })
+                .collect(),
+            disruption: s.disruption.map(|disruption| Disruption {
+                category: disruption.category,
+                description: disruption.description,
+                additional_info: disruption.additional_info,
+                closure_text: disruption.closure_text,
+            }),
         })
         .collect::<Vec<_>>();
     statuses.sort();
@@ -48,25 +112,25 @@ pub fn try_parse_line_status(
 fn from_tfl_line_status(status_severity: i32) -> LineState {
     match status_severity {
         0 => LineState::ReducedService, // Special service
-        // 1 => Closed,
+        1 => LineState::Closed,
         2 => LineState::Suspended,
         3 => LineState::PartSuspended,
         4 => LineState::PlannedClosure,
         5 => LineState::PartClosure,
         6 => LineState::SevereDelays,
         7 => LineState::ReducedService,
-        // 8 => Bus service
+        8 => LineState::BusService,
         9 => LineState::MinorDelays,
         10 => LineState::GoodService,
-        // 11 => Part closed
-        // 12 => Exit only
-        // 13 => No step free access
-        // 14 => Change of frequency
-        // 15 => Diverted
-        // 16 => Not running
-        // 17 => Issues reported
-        // 18 => No Issues
-        // 19 => Information
+        11 => LineState::PartClosed,
+        12 => LineState::ExitOnly,
+        13 => LineState::NoStepFreeAccess,
+        14 => LineState::ChangeOfFrequency,
+        15 => LineState::Diverted,
+        16 => LineState::NotRunning,
+        17 => LineState::IssuesReported,
+        18 => LineState::NoIssues,
+        19 => LineState::Information,
         20 => LineState::ServiceClosed,
         _ => LineState::Other,
     }
@@ -74,14 +138,12 @@ fn from_tfl_line_status(status_severity: i32) -> LineState {
 
 #[derive(Deserialize, Debug, Clone)]
 struct TflStationStatus {
-    #[serde(rename = "atcoCode")]
-    pub atco_code: String,
     #[serde(rename = "type")]
     pub type_str: String,
     pub description: String,
 }
 
-pub fn try_parse_station_status(line_id: &str, values: &Vec<Value>) -> Option<Vec<StationStatus>> {
+pub fn try_parse_station_status(line_id: &str, values: &[Value]) -> Option<Vec<StationStatus>> {
     let mut statuses: Vec<StationStatus> = values
         .iter()
         .map(|value| {
@@ -119,6 +181,9 @@ fn from_tfl_station_status(tfl_type: &str) -> StationState {
 pub struct StopPointModeResponse {
     #[serde(rename = "stopPoints")]
     pub stop_points: Vec<StopPointDetails>,
+    /// The total number of stop points, when they're split into pages.
+    #[serde(default)]
+    pub total: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -129,3 +194,99 @@ pub struct StopPointDetails {
     #[serde(rename = "stationNaptan", default)]
     pub station_naptan: Option<String>,
 }
+
+/// Fields of a TfL line status response which are used when parsing it.
+const LINE_FIELDS: &[&str] = &["id", "name", "modeName", "lineStatuses"];
+const LINE_STATUS_FIELDS: &[&str] = &["statusSeverity", "reason", "validityPeriods", "disruption"];
+const DISRUPTION_FIELDS: &[&str] = &["category", "description", "additionalInfo", "closureText"];
+
+/// Fields of a TfL station disruption which are used when parsing or grouping it.
+const STATION_FIELDS: &[&str] = &["stationAtcoCode", "atcoCode", "type", "description"];
+
+/// Strips a raw line status down to the fields that are used by [`try_parse_line_status`].
+pub fn strip_line_status(value: &Value) -> Value {
+    let mut stripped = retain_fields(value, LINE_FIELDS);
+    if let Some(Value::Array(statuses)) = stripped.get_mut("lineStatuses") {
+        for status in statuses.iter_mut() {
+            *status = retain_fields(status, LINE_STATUS_FIELDS);
+            if let Some(disruption) = status.get_mut("disruption") {
+                *disruption = retain_fields(disruption, DISRUPTION_FIELDS);
+            }
+        }
+    }
+    stripped
+}
+
+/// Strips raw station disruptions down to the fields that are used by
+/// [`try_parse_station_status`].
+pub fn strip_station_status(values: &[Value]) -> Vec<Value> {
+    values
+        .iter()
+        .map(|value| retain_fields(value, STATION_FIELDS))
+        .collect()
+}
+
+fn retain_fields(value: &Value, fields: &[&str]) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(k, _)| fields.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_every_tfl_status_severity() {
+        let expected = [
+            (0, LineState::ReducedService),
+            (1, LineState::Closed),
+            (2, LineState::Suspended),
+            (3, LineState::PartSuspended),
+            (4, LineState::PlannedClosure),
+            (5, LineState::PartClosure),
+            (6, LineState::SevereDelays),
+            (7, LineState::ReducedService),
+            (8, LineState::BusService),
+            (9, LineState::MinorDelays),
+            (10, LineState::GoodService),
+            (11, LineState::PartClosed),
+            (12, LineState::ExitOnly),
+            (13, LineState::NoStepFreeAccess),
+            (14, LineState::ChangeOfFrequency),
+            (15, LineState::Diverted),
+            (16, LineState::NotRunning),
+            (17, LineState::IssuesReported),
+            (18, LineState::NoIssues),
+            (19, LineState::Information),
+            (20, LineState::ServiceClosed),
+        ];
+        for (severity, state) in expected {
+            assert_eq!(
+                from_tfl_line_status(severity),
+                state,
+                "severity {}",
+                severity
+            );
+        }
+        assert_eq!(from_tfl_line_status(-1), LineState::Other);
+        assert_eq!(from_tfl_line_status(21), LineState::Other);
+
+        // Every state can come from TfL
+        for state in LineState::ALL {
+            assert!(
+                *state == LineState::Other
+                    || expected.iter().any(|(_, expected)| expected == state),
+                "{:?} isn't mapped from any severity",
+                state
+            );
+        }
+    }
+}
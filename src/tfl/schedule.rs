@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::config::PollingConfig;
+use crate::localtime;
+use crate::types::{CurrentLineStatus, LineState, LineStatus};
+
+/// The kinds of status loaded from TfL, which are polled independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollTarget {
+    Lines,
+    Stations,
+}
+
+impl PollTarget {
+    /// What the target is called in the store and the API.
+    pub fn name(self) -> &'static str {
+        match self {
+            PollTarget::Lines => "lines",
+            PollTarget::Stations => "stations",
+        }
+    }
+}
+
+/// How busy the network is, which decides how often to poll when polling is adaptive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    /// During peak hours, or while any line is in a severe state.
+    Busy,
+    Normal,
+    /// Overnight, when most lines report `ServiceClosed`.
+    Quiet,
+}
+
+/// How long to wait before polling a target again, given the latest line status.
+pub fn interval(
+    config: &PollingConfig,
+    target: PollTarget,
+    now: OffsetDateTime,
+    lines: Option<&HashMap<String, CurrentLineStatus>>,
+) -> Duration {
+    let target_config = match target {
+        PollTarget::Lines => &config.lines,
+        PollTarget::Stations => &config.stations,
+    };
+    let secs = match pace(config, now, lines) {
+        Pace::Busy => target_config.busy_interval_secs,
+        Pace::Normal => target_config.interval_secs,
+        Pace::Quiet => target_config.quiet_interval_secs,
+    };
+    Duration::from_secs(secs)
+}
+
+pub fn pace(
+    config: &PollingConfig,
+    now: OffsetDateTime,
+    lines: Option<&HashMap<String, CurrentLineStatus>>,
+) -> Pace {
+    if !config.adaptive {
+        return Pace::Normal;
+    }
+    let local = localtime::to_london(now);
+    let peak = config.peak_days.contains(&local.weekday())
+        && config
+            .peak_hours
+            .iter()
+            .any(|hours| hours.contains(local.time()));
+    let lines = lines
+        .map(|lines| lines.values().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let severe = lines.iter().any(|line| {
+        line.statuses
+            .iter()
+            .any(|status| is_current(status) && config.severe_states.contains(&status.status))
+    });
+    if peak || severe {
+        return Pace::Busy;
+    }
+    let closed = lines
+        .iter()
+        .filter(|line| {
+            line.statuses
+                .iter()
+                .any(|status| status.status == LineState::ServiceClosed)
+        })
+        .count();
+    if closed * 2 > lines.len() {
+        Pace::Quiet
+    } else {
+        Pace::Normal
+    }
+}
+
+/// Whether a status applies now, rather than announcing planned works.
+fn is_current(status: &LineStatus) -> bool {
+    status.validity_periods.is_empty() || status.validity_periods.iter().any(|period| period.is_now)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::format_description;
+
+    use super::*;
+    use crate::types::ValidityPeriod;
+
+    fn at(time: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(time, &format_description::well_known::Rfc3339).unwrap()
+    }
+
+    /// Noon on Wednesday 21 October 2026, when London is on BST.
+    fn weekday_noon() -> OffsetDateTime {
+        at("2026-10-21T11:00:00Z")
+    }
+
+    fn config() -> PollingConfig {
+        PollingConfig {
+            adaptive: true,
+            ..Default::default()
+        }
+    }
+
+    fn lines(states: &[LineState]) -> HashMap<String, CurrentLineStatus> {
+        states
+            .iter()
+            .enumerate()
+            .map(|(i, state)| {
+                let status = LineStatus {
+                    status: *state,
+                    reason: None,
+                    validity_periods: vec![],
+                    disruption: None,
+                };
+                let line = CurrentLineStatus {
+                    mode: "tube".to_string(),
+                    statuses: vec![status],
+                };
+                (format!("line-{}", i), line)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn polls_at_the_normal_interval_unless_adaptive() {
+        let lines = lines(&[LineState::SevereDelays]);
+        let config = PollingConfig::default();
+        let morning_peak = at("2026-10-21T07:30:00Z");
+        assert_eq!(pace(&config, morning_peak, Some(&lines)), Pace::Normal);
+        assert_eq!(
+            interval(&config, PollTarget::Stations, morning_peak, Some(&lines)),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn polls_more_often_at_weekday_peaks() {
+        let config = config();
+        let good = lines(&[LineState::GoodService]);
+        // 08:30 in London
+        let morning_peak = at("2026-10-21T07:30:00Z");
+        assert_eq!(pace(&config, morning_peak, Some(&good)), Pace::Busy);
+        assert_eq!(
+            interval(&config, PollTarget::Lines, morning_peak, Some(&good)),
+            Duration::from_secs(30)
+        );
+        assert_eq!(pace(&config, weekday_noon(), Some(&good)), Pace::Normal);
+        let saturday_morning = at("2026-10-24T07:30:00Z");
+        assert_eq!(pace(&config, saturday_morning, Some(&good)), Pace::Normal);
+    }
+
+    #[test]
+    fn polls_more_often_while_a_line_is_severely_disrupted() {
+        let config = config();
+        let severe = lines(&[LineState::GoodService, LineState::Suspended]);
+        assert_eq!(pace(&config, weekday_noon(), Some(&severe)), Pace::Busy);
+
+        // Planned closures don't count until they start
+        let mut planned = lines(&[LineState::Closed]);
+        planned.get_mut("line-0").unwrap().statuses[0].validity_periods = vec![ValidityPeriod {
+            from: at("2026-10-24T04:30:00Z"),
+            to: at("2026-10-25T23:59:00Z"),
+            is_now: false,
+        }];
+        assert_eq!(pace(&config, weekday_noon(), Some(&planned)), Pace::Normal);
+    }
+
+    #[test]
+    fn polls_less_often_when_most_lines_are_closed_for_the_night() {
+        let config = config();
+        let night = at("2026-10-21T02:00:00Z");
+        let closed = lines(&[
+            LineState::ServiceClosed,
+            LineState::ServiceClosed,
+            LineState::GoodService,
+        ]);
+        assert_eq!(pace(&config, night, Some(&closed)), Pace::Quiet);
+        assert_eq!(
+            interval(&config, PollTarget::Stations, night, Some(&closed)),
+            Duration::from_secs(300)
+        );
+
+        let night_tube = lines(&[LineState::ServiceClosed, LineState::GoodService]);
+        assert_eq!(pace(&config, night, Some(&night_tube)), Pace::Normal);
+        assert_eq!(pace(&config, night, None), Pace::Normal);
+    }
+}
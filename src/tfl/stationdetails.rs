@@ -1,18 +1,17 @@
 use super::{api::Api, parser::StopPointDetails};
 use rocket::tokio::sync::RwLock;
 use std::sync::Arc;
-use std::time::Instant;
 
 #[derive(Debug)]
 enum LoadState {
     /// Not loaded yet
     NotLoaded,
-    /// Currently loading (with timestamp of when loading started)
-    Loading(Instant),
+    /// Currently loading
+    Loading,
     /// Successfully loaded
     Loaded(Vec<StopPointDetails>),
-    /// Failed to load (with error message)
-    Failed(String),
+    /// Failed to load
+    Failed,
 }
 
 /// Manages asynchronous loading of station details
@@ -36,21 +35,19 @@ impl LoadedStationDetails {
         match &*state {
             LoadState::Loaded(details) => {
                 // If we already have details, return them immediately
-                return Ok(details.clone());
+                Ok(details.clone())
             }
-            LoadState::Loading(_) => {
+            LoadState::Loading => {
                 // If a load is in progress, tell the user to try again later
-                return Err(
-                    "Station details are currently loading, please try again later".to_string(),
-                );
+                Err("Station details are currently loading, please try again later".to_string())
             }
-            LoadState::Failed(_) => {
+            LoadState::Failed => {
                 // If previous loading failed, try to load again
                 drop(state); // Release read lock
 
                 // Initiate a load
                 let mut state = self.state.write().await;
-                *state = LoadState::Loading(Instant::now());
+                *state = LoadState::Loading;
                 drop(state);
 
                 self.perform_load().await // Start a new load attempt
@@ -61,7 +58,7 @@ impl LoadedStationDetails {
 
                 // Initiate a load
                 let mut state = self.state.write().await;
-                *state = LoadState::Loading(Instant::now());
+                *state = LoadState::Loading;
                 drop(state);
 
                 self.perform_load().await // Start a new load attempt
@@ -83,7 +80,7 @@ impl LoadedStationDetails {
                 // Record the failure
                 let error_msg = format!("Failed to load station details: {:?}", e);
                 log::error!("{}", error_msg);
-                *self.state.write().await = LoadState::Failed(error_msg.clone());
+                *self.state.write().await = LoadState::Failed;
                 Err(error_msg)
             }
         }
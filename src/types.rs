@@ -1,38 +1,154 @@
+use std::collections::HashMap;
+
+use rocket::FromFormField;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use time::OffsetDateTime;
+use time::{OffsetDateTime, Time, Weekday};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
 pub struct LineStatus {
     pub status: LineState,
     pub reason: Option<String>,
+    /// When TfL says that the status applies, which can include future periods for planned
+    /// works. These are as of when the status was first seen.
+    #[serde(default)]
+    pub validity_periods: Vec<ValidityPeriod>,
+    #[serde(default)]
+    pub disruption: Option<Disruption>,
 }
 
 pub struct LineMetadata {
     pub mode: String,
 }
 
+/// A period during which a TfL line status applies, which may be in the future.
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidityPeriod {
+    #[serde(with = "time::serde::rfc3339")]
+    pub from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub to: OffsetDateTime,
+    pub is_now: bool,
+}
+
+/// TfL's details of the disruption behind a line status.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub struct Disruption {
+    /// Like `RealTime` or `PlannedWork`.
+    pub category: Option<String>,
+    pub description: Option<String>,
+    pub additional_info: Option<String>,
+    /// Like `partClosure` or `severeDelays`.
+    pub closure_text: Option<String>,
+}
+
+/// The states of a line, in order from the most to the least severe so that they can be
+/// compared. They're stored and serialized by name, so new states can go anywhere in the order,
+/// but they must be added to `ALL` in the same place.
+///
+/// `ServiceClosed` comes after the closures and before `SevereDelays`, since no trains are
+/// running. Unlike the closures though, it's what every line reports outside its operating hours,
+/// so it isn't a disruption: filters by severity should use `is_disruption_at_least`, which never
+/// counts it, rather than comparing states directly.
+#[derive(
+    Serialize, Deserialize, FromFormField, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
 pub enum LineState {
+    Closed,
     Suspended,
+    NotRunning,
     PartSuspended,
     PlannedClosure,
     PartClosure,
+    PartClosed,
     ServiceClosed,
     SevereDelays,
+    BusService,
     ReducedService,
+    Diverted,
+    ChangeOfFrequency,
     MinorDelays,
+    IssuesReported,
+    ExitOnly,
+    NoStepFreeAccess,
+    Information,
     GoodService,
+    NoIssues,
     Other,
 }
 
+impl LineState {
+    /// Every state, from the most to the least severe.
+    pub const ALL: &'static [LineState] = &[
+        LineState::Closed,
+        LineState::Suspended,
+        LineState::NotRunning,
+        LineState::PartSuspended,
+        LineState::PlannedClosure,
+        LineState::PartClosure,
+        LineState::PartClosed,
+        LineState::ServiceClosed,
+        LineState::SevereDelays,
+        LineState::BusService,
+        LineState::ReducedService,
+        LineState::Diverted,
+        LineState::ChangeOfFrequency,
+        LineState::MinorDelays,
+        LineState::IssuesReported,
+        LineState::ExitOnly,
+        LineState::NoStepFreeAccess,
+        LineState::Information,
+        LineState::GoodService,
+        LineState::NoIssues,
+        LineState::Other,
+    ];
+
+    /// Whether a line in this state is disrupted at least as severely as `min_severity`.
+    /// `ServiceClosed` is when a line isn't running outside its timetabled hours, like every
+    /// night, so it never counts as a disruption whatever the threshold.
+    pub fn is_disruption_at_least(self, min_severity: LineState) -> bool {
+        self != LineState::ServiceClosed && self <= min_severity
+    }
+
+    /// The name that TfL uses for the state, like `Severe Delays`.
+    pub fn description(self) -> &'static str {
+        match self {
+            LineState::Closed => "Closed",
+            LineState::Suspended => "Suspended",
+            LineState::NotRunning => "Not Running",
+            LineState::PartSuspended => "Part Suspended",
+            LineState::PlannedClosure => "Planned Closure",
+            LineState::PartClosure => "Part Closure",
+            LineState::PartClosed => "Part Closed",
+            LineState::ServiceClosed => "Service Closed",
+            LineState::SevereDelays => "Severe Delays",
+            LineState::BusService => "Bus Service",
+            LineState::ReducedService => "Reduced Service",
+            LineState::Diverted => "Diverted",
+            LineState::ChangeOfFrequency => "Change of frequency",
+            LineState::MinorDelays => "Minor Delays",
+            LineState::IssuesReported => "Issues Reported",
+            LineState::ExitOnly => "Exit Only",
+            LineState::NoStepFreeAccess => "No Step Free Access",
+            LineState::Information => "Information",
+            LineState::GoodService => "Good Service",
+            LineState::NoIssues => "No Issues",
+            LineState::Other => "Other",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StationStatus {
     pub status: StationState,
     pub description: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Serialize, Deserialize, FromFormField, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
 pub enum StationState {
     Closure,
     PartClosure,
@@ -41,16 +157,171 @@ pub enum StationState {
     Other,
 }
 
+impl StationState {
+    /// Every state, from the most to the least severe.
+    pub const ALL: &'static [StationState] = &[
+        StationState::Closure,
+        StationState::PartClosure,
+        StationState::InterchangeMessage,
+        StationState::Information,
+        StationState::Other,
+    ];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            StationState::Closure => "Closure",
+            StationState::PartClosure => "Part Closure",
+            StationState::InterchangeMessage => "Interchange Message",
+            StationState::Information => "Information",
+            StationState::Other => "Other",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct LineStatusHistoryEntry {
     pub start_time: OffsetDateTime,
     pub end_time: Option<OffsetDateTime>,
-    pub data: Value,
+    pub mode: String,
+    pub statuses: Vec<LineStatus>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct StationStatusHistoryEntry {
     pub start_time: OffsetDateTime,
     pub end_time: Option<OffsetDateTime>,
-    pub data: Vec<Value>,
+    pub statuses: Vec<StationStatus>,
+}
+
+/// A period when a kind of status wasn't being collected, so nothing is known about it. Gaps
+/// which are still going on have no end time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionGap {
+    pub start_time: OffsetDateTime,
+    pub end_time: Option<OffsetDateTime>,
+}
+
+/// The most recently polled status of every line and disrupted station.
+#[derive(Debug, Clone)]
+pub struct CurrentStatus {
+    pub updated: OffsetDateTime,
+    pub lines: HashMap<String, CurrentLineStatus>,
+    pub stations: HashMap<String, Vec<StationStatus>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CurrentLineStatus {
+    pub mode: String,
+    pub statuses: Vec<LineStatus>,
+}
+
+/// A line's open history row being replaced because its status changed.
+#[derive(Debug, Clone)]
+pub struct LineTransition {
+    pub line: String,
+    pub time: OffsetDateTime,
+    pub mode: Option<String>,
+    /// `None` if the line had no open row, or its status couldn't be parsed.
+    pub previous: Option<Vec<LineStatus>>,
+    /// `None` if the new status couldn't be parsed.
+    pub current: Option<Vec<LineStatus>>,
+}
+
+/// A station's disruptions changing. Stations without any disruptions have empty statuses.
+#[derive(Debug, Clone)]
+pub struct StationTransition {
+    pub station: String,
+    pub time: OffsetDateTime,
+    pub previous: Vec<StationStatus>,
+    pub current: Vec<StationStatus>,
+}
+
+/// A rule for alerting someone when any of a set of lines enters a sufficiently severe state.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub name: String,
+    /// The lines to watch, or every line if empty.
+    pub lines: Vec<String>,
+    pub min_severity: LineState,
+    /// The time window (in London time) during which alerts are sent. If `active_from` is after
+    /// `active_until` then the window spans midnight. Alerts are always sent if these are unset.
+    pub active_from: Option<Time>,
+    pub active_until: Option<Time>,
+    /// The days on which alerts are sent, or every day if empty.
+    pub days: Vec<Weekday>,
+    pub target: NotificationTarget,
+}
+
+/// Where to send a subscription's alerts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum NotificationTarget {
+    /// A signed POST, in the same way as the webhooks.
+    Webhook { url: String, secret: String },
+    /// An email sent through the configured SMTP relay. Also receives the daily digest.
+    Email { address: String },
+}
+
+/// A browser registered for Web Push notifications. If any lines or stations are listed, only
+/// changes to those are sent, otherwise everything is.
+#[derive(Debug, Clone)]
+pub struct PushSubscription {
+    /// The push service URL, which uniquely identifies the registration.
+    pub endpoint: String,
+    /// The browser's P-256 public key and authentication secret, base64url encoded.
+    pub p256dh: String,
+    pub auth: String,
+    pub lines: Vec<String>,
+    pub stations: Vec<String>,
+    /// Only send line changes where the old or new status is at least this severe.
+    pub min_line_severity: Option<LineState>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_closed_is_never_a_disruption() {
+        assert!(LineState::Suspended.is_disruption_at_least(LineState::SevereDelays));
+        assert!(LineState::SevereDelays.is_disruption_at_least(LineState::SevereDelays));
+        assert!(!LineState::MinorDelays.is_disruption_at_least(LineState::SevereDelays));
+        for min_severity in LineState::ALL {
+            assert!(!LineState::ServiceClosed.is_disruption_at_least(*min_severity));
+        }
+    }
+
+    #[test]
+    fn lists_every_line_state_in_order() {
+        // In the derived order, without duplicates
+        assert!(LineState::ALL.windows(2).all(|pair| pair[0] < pair[1]));
+        for state in LineState::ALL {
+            // Stops compiling when a state is added, as a reminder to add it to `ALL` and to
+            // count it below
+            match state {
+                LineState::Closed
+                | LineState::Suspended
+                | LineState::NotRunning
+                | LineState::PartSuspended
+                | LineState::PlannedClosure
+                | LineState::PartClosure
+                | LineState::PartClosed
+                | LineState::ServiceClosed
+                | LineState::SevereDelays
+                | LineState::BusService
+                | LineState::ReducedService
+                | LineState::Diverted
+                | LineState::ChangeOfFrequency
+                | LineState::MinorDelays
+                | LineState::IssuesReported
+                | LineState::ExitOnly
+                | LineState::NoStepFreeAccess
+                | LineState::Information
+                | LineState::GoodService
+                | LineState::NoIssues
+                | LineState::Other => {}
+            }
+        }
+        assert_eq!(LineState::ALL.len(), 21);
+    }
 }
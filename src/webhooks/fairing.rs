@@ -0,0 +1,38 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::spawn;
+use rocket::{Orbit, Rocket};
+
+use super::Webhooks;
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::store::Store;
+
+pub struct WebhookFairing;
+
+impl WebhookFairing {
+    pub fn new() -> Self {
+        WebhookFairing
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for WebhookFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Webhook Delivery Task",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.state::<Config>().unwrap();
+        let webhooks = Webhooks::new(
+            config.webhooks.clone(),
+            rocket.state::<Store>().unwrap().clone(),
+        );
+        let events = rocket.state::<EventBus>().unwrap().clone();
+        spawn(async move {
+            webhooks.start(events).await;
+        });
+    }
+}
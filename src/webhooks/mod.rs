@@ -0,0 +1,310 @@
+mod fairing;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use rocket::tokio::{self, sync::broadcast::error::RecvError};
+use serde::Serialize;
+use sha2::Sha256;
+use time::{format_description, OffsetDateTime};
+
+use crate::config::{WebhookConfig, WebhookTarget};
+use crate::events::{EventBus, StatusEvent};
+use crate::store::{Store, WebhookDeadLetter};
+use crate::types::{LineStatus, StationStatus, StationTransition};
+
+pub use fairing::WebhookFairing;
+
+/// Header containing the hex encoded HMAC-SHA256 of the request body, prefixed with `sha256=`.
+pub const SIGNATURE_HEADER: &str = "X-Severe-Delays-Signature";
+
+/// Notifies webhooks when the state of a line or station changes.
+pub struct Webhooks {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    store: Store,
+}
+
+/// The body of each webhook request, which matches the events sent by `/api/v1/events`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum WebhookPayload<'a> {
+    Line {
+        line: &'a str,
+        time: String,
+        mode: Option<&'a str>,
+        previous: Option<&'a [LineStatus]>,
+        current: Option<&'a [LineStatus]>,
+    },
+    Station {
+        station: &'a str,
+        time: String,
+        previous: &'a [StationStatus],
+        current: &'a [StationStatus],
+    },
+}
+
+impl Webhooks {
+    pub fn new(config: WebhookConfig, store: Store) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap();
+        Webhooks {
+            config,
+            client,
+            store,
+        }
+    }
+
+    pub async fn start(self, events: EventBus) {
+        let webhooks = Arc::new(self);
+        let mut receiver = events.subscribe();
+        loop {
+            match receiver.recv().await {
+                Ok(event) => webhooks.dispatch(&event).await,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Webhooks fell behind, skipped {} events", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Starts delivering `event` to each webhook that's interested in it.
+    async fn dispatch(self: &Arc<Self>, event: &StatusEvent) {
+        if !event.is_state_change() {
+            return;
+        }
+        let payload = match serde_json::to_string(&WebhookPayload::from(event)) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Failed to serialize webhook payload: {:?}", err);
+                return;
+            }
+        };
+        for (webhook_id, target) in self.targets().await {
+            if !target_matches(&target, event) {
+                continue;
+            }
+            let webhooks = self.clone();
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                webhooks.deliver(webhook_id, &target, &payload).await;
+            });
+        }
+    }
+
+    /// The configured webhooks, followed by those registered through the API.
+    async fn targets(&self) -> Vec<(Option<i64>, WebhookTarget)> {
+        let mut targets = self
+            .config
+            .hooks
+            .iter()
+            .map(|target| (None, target.clone()))
+            .collect::<Vec<_>>();
+        match self.store.get_connection().await {
+            Ok(mut connection) => match connection.get_webhooks().await {
+                Ok(registered) => targets.extend(
+                    registered
+                        .into_iter()
+                        .map(|webhook| (Some(webhook.id), webhook.target)),
+                ),
+                Err(err) => warn!("Failed to load webhooks: {:?}", err),
+            },
+            Err(err) => warn!(
+                "Failed to acquire DB connection to load webhooks: {:?}",
+                err
+            ),
+        }
+        targets
+    }
+
+    /// Sends the payload, and records it as a dead letter if every attempt fails.
+    async fn deliver(&self, webhook_id: Option<i64>, target: &WebhookTarget, payload: &str) {
+        let result = send_with_retries(
+            &self.client,
+            &self.config,
+            &target.url,
+            &target.secret,
+            payload,
+        )
+        .await;
+        if let Err(failure) = result {
+            self.record_dead_letter(webhook_id, target, payload, failure)
+                .await;
+        }
+    }
+
+    async fn record_dead_letter(
+        &self,
+        webhook_id: Option<i64>,
+        target: &WebhookTarget,
+        payload: &str,
+        failure: DeliveryFailure,
+    ) {
+        let dead_letter = WebhookDeadLetter {
+            webhook_id,
+            url: target.url.clone(),
+            payload: payload.to_string(),
+            error: failure.error,
+            attempts: failure.attempts.into(),
+            failed_at: OffsetDateTime::now_utc(),
+        };
+        let result = match self.store.get_connection().await {
+            Ok(mut connection) => connection
+                .add_webhook_dead_letter(&dead_letter)
+                .await
+                .map_err(|err| format!("{:?}", err)),
+            Err(err) => Err(format!("{:?}", err)),
+        };
+        if let Err(err) = result {
+            warn!(
+                "Failed to record failed webhook delivery to {}: {}",
+                target.url, err
+            );
+        }
+    }
+}
+
+/// The last error from a delivery which failed every attempt.
+#[derive(Debug)]
+pub struct DeliveryFailure {
+    pub error: String,
+    pub attempts: u32,
+}
+
+/// Sends a signed payload, retrying with exponential backoff until it succeeds or runs out of
+/// attempts.
+pub async fn send_with_retries(
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    url: &str,
+    secret: &str,
+    payload: &str,
+) -> Result<(), DeliveryFailure> {
+    let mut backoff = Duration::from_millis(config.initial_backoff_ms);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let error = match send(client, url, secret, payload).await {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        if attempts >= config.max_attempts {
+            warn!(
+                "Giving up on webhook {} after {} attempts: {}",
+                url, attempts, error
+            );
+            return Err(DeliveryFailure { error, attempts });
+        }
+        debug!(
+            "Webhook {} failed (attempt {}), retrying in {:?}: {}",
+            url, attempts, backoff, error
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// POSTs a signed payload, failing if it doesn't respond with a success status.
+pub async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    payload: &str,
+) -> Result<(), String> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(secret, payload))
+        .body(payload.to_string())
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("Unexpected status {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Signs a payload in the format sent in the signature header.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    let signature = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("sha256={}", signature)
+}
+
+fn target_matches(target: &WebhookTarget, event: &StatusEvent) -> bool {
+    let unfiltered =
+        target.lines.is_empty() && target.modes.is_empty() && target.stations.is_empty();
+    match event {
+        StatusEvent::Line(transition) => {
+            let included = unfiltered
+                || target.lines.contains(&transition.line)
+                || transition
+                    .mode
+                    .as_ref()
+                    .is_some_and(|mode| target.modes.contains(mode));
+            let severe_enough = target.min_line_severity.is_none_or(|min_severity| {
+                transition
+                    .previous
+                    .iter()
+                    .chain(transition.current.iter())
+                    .flatten()
+                    .any(|status| status.status.is_disruption_at_least(min_severity))
+            });
+            included && severe_enough
+        }
+        StatusEvent::Station(transition) => {
+            let included = unfiltered || target.stations.contains(&transition.station);
+            let severe_enough = target.min_station_severity.is_none_or(|min_severity| {
+                transition
+                    .previous
+                    .iter()
+                    .chain(transition.current.iter())
+                    .any(|status| status.status <= min_severity)
+            });
+            included && severe_enough
+        }
+    }
+}
+
+impl<'a> From<&'a StatusEvent> for WebhookPayload<'a> {
+    fn from(event: &'a StatusEvent) -> Self {
+        match event {
+            StatusEvent::Line(transition) => WebhookPayload::Line {
+                line: &transition.line,
+                time: format_time(transition.time),
+                mode: transition.mode.as_deref(),
+                previous: transition.previous.as_deref(),
+                current: transition.current.as_deref(),
+            },
+            StatusEvent::Station(StationTransition {
+                station,
+                time,
+                previous,
+                current,
+            }) => WebhookPayload::Station {
+                station,
+                time: format_time(*time),
+                previous,
+                current,
+            },
+        }
+    }
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    time.format(&format_description::well_known::Rfc3339)
+        .unwrap()
+}